@@ -9,74 +9,142 @@ const TEST_PREFIX: &str = "
 
 use std::io::{Cursor, Read, prelude::*};
 use std::fs::File;
+use std::sync::{Arc, Mutex};
 
+use super::cell;
 use super::jit;
+use super::random;
+use super::reader;
 use super::space;
+use super::trace;
 
 struct BufferIO {
-    input: Cursor<String>,
+    input: reader::Reader<Cursor<Vec<u8>>>,
     output: Cursor<Vec<u8>>,
+    prompts: Vec<u8>,
 }
 
 impl BufferIO {
-    pub fn new() -> Self {
+    pub fn new(input: Vec<u8>) -> Self {
         BufferIO {
-            input: Cursor::new(String::new()),
+            input: reader::Reader::new(Cursor::new(input)),
             output: Cursor::new(Vec::new()),
+            prompts: Vec::new(),
         }
     }
 }
 
 impl jit::IO for BufferIO {
-    fn input_char(&mut self) -> Option<u8> {
-        let mut buf = [0; 1];
-
-        // NOTE: unwrap might cause issues here
-        if let Ok(()) = self.input.read_exact(&mut buf) {
-            Some(buf[0])
-        } else {
-            None
-        }
+    fn input_char(&mut self) -> std::io::Result<Option<u8>> {
+        self.input.read_char()
     }
 
-    fn input_number(&mut self) -> isize {
-        let mut text = String::new();
-        self.input
-            .read_line(&mut text)
-            .expect(\"Failed to read a line\");
-        text.trim()
-            .parse::<isize>()
-            .expect(\"Failed to read a number\")
+    fn input_number(&mut self) -> std::io::Result<Option<isize>> {
+        self.input.read_number()
     }
 
-    fn output_char(&mut self, c: u8) {
+    fn output_char(&mut self, c: u8) -> std::io::Result<()> {
         let buf = [c; 1];
-        self.output.write_all(&buf).unwrap();
-        self.output.flush().unwrap();
+        self.output.write_all(&buf)?;
+        self.output.flush()
+    }
+
+    fn output_number(&mut self, n: &dyn std::fmt::Display) -> std::io::Result<()> {
+        self.output.write_fmt(format_args!(\"{}\", n))?;
+        self.output.flush()
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+
+    fn prompt(&mut self, prompt: &str) -> std::io::Result<()> {
+        self.prompts.extend(prompt.bytes());
+        Ok(())
+    }
+}
+
+// Somewhere for a `trace::Tracer` to write that can still be read once the `Jit` is done with it.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
     }
 
-    fn output_number(&mut self, n: isize) {
-        self.output.write_fmt(format_args!(\"{}\", n)).unwrap();
-        self.output.flush().unwrap();
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
     }
 }
 
 ";
 
-// TODO: this currently won't cause a failure if tests fail to terminate, and will just hang
-// instead.
+// Every test runs with a budget, so that a program that fails to terminate fails its test instead
+// of hanging it. A `.bf.max-steps` file lowers the step limit, and a `.bf.status` file gives the
+// exit status expected instead of `Terminated`. A `.bf.prompts` file holds everything the program
+// should ask the user, such as for the result of dividing by zero.
 const TEST_TEMPLATE: &str = "
-#[test]
-fn test_%PREFIX%() {
+fn jit_%PREFIX%(interpret: bool) -> jit::Jit<BufferIO, %CELL%> {
     let prog = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf\").expect(\"Failed to read test file\");
 
-    let mut io = BufferIO::new();
-    if let Ok(mut file) = File::open(\"%ROOT%/tests/%PREFIX%.bf.input\") {
-        file.read_to_string(io.input.get_mut()).expect(\"Failed to read input\");
+    let input = std::fs::read(\"%ROOT%/tests/%PREFIX%.bf.input\").unwrap_or_default();
+    let io = BufferIO::new(input);
+
+    let mut options = jit::Options::default();
+    if let Ok(dialect) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.dialect\") {
+        options.dialect = dialect.trim().parse().unwrap();
     }
+    if let Ok(behavior) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.division-by-zero\") {
+        options.division_by_zero = Some(behavior.trim().parse().unwrap());
+    }
+    if let Ok(overflow) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.overflow\") {
+        options.overflow = overflow.trim().parse().unwrap();
+    }
+    if let Ok(seed) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.seed\") {
+        options.seed = Some(seed.trim().parse().unwrap());
+    }
+    options.max_steps = Some(100_000_000);
+    options.timeout = Some(std::time::Duration::from_secs(10));
+    if let Ok(max_steps) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.max-steps\") {
+        options.max_steps = Some(max_steps.trim().parse().unwrap());
+    }
+    if let Ok(max_stack) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.max-stack\") {
+        options.max_stack = Some(max_stack.trim().parse().unwrap());
+    }
+    if let Ok(max_code_size) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.max-code-size\") {
+        options.max_code_size = Some(max_code_size.trim().parse().unwrap());
+    }
+    options.interpret = interpret;
+    // blocks are compiled straight away, unless a test is about tiering
+    options.compile_threshold = 0;
+    if let Ok(threshold) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.compile-threshold\") {
+        options.compile_threshold = threshold.trim().parse().unwrap();
+    }
+
+    let mut jit = jit::Jit::with_options(space::Funge93::<%CELL%>::from_string(&prog), io, options);
+    if let Ok(directions) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.directions\") {
+        let directions: random::Scripted = directions.parse().unwrap();
+        jit.set_directions(Box::new(directions));
+    }
+
+    jit
+}
+
+#[test]
+fn test_%PREFIX%() {
+    let mut jit = jit_%PREFIX%(false);
+    let result = jit.run();
 
-    let mut jit = jit::Jit::new(space::Funge93::from_string(&prog), io);
-    jit.run();
+    if let Ok(expected) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.error\") {
+        let actual = result.expect_err(\"Expected an error\").to_string();
+        assert_eq!(expected.trim(), actual);
+    } else {
+        let expected = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.status\")
+            .unwrap_or_else(|_| String::from(\"Terminated\"));
+        let actual = format!(\"{:?}\", result.expect(\"Unexpected error\"));
+        assert_eq!(expected.trim(), actual);
+    }
 
     if let Ok(mut file) = File::open(\"%ROOT%/tests/%PREFIX%.bf.output\") {
         let mut expected = String::new();
@@ -88,9 +156,103 @@ fn test_%PREFIX%() {
             actual: &actual,
         });
     }
+
+    if let Ok(expected) = std::fs::read_to_string(\"%ROOT%/tests/%PREFIX%.bf.prompts\") {
+        let actual = String::from_utf8(jit.io.prompts.clone()).unwrap();
+        assert_eq!(expected, actual, \"prompts\");
+    }
+}
+";
+
+// Runs a test under the interpreter as well as the JIT, checking that they agree on everything the
+// program did. Tests that limit the size of compiled code are skipped, as the interpreter doesn't
+// compile anything.
+const DIFFERENTIAL_TEMPLATE: &str = "
+#[test]
+fn test_%PREFIX%_differential() {
+    let mut compiled = jit_%PREFIX%(false);
+    let mut interpreted = jit_%PREFIX%(true);
+    let compiled_result = compiled.run().map_err(|err| err.to_string());
+    let interpreted_result = interpreted.run().map_err(|err| err.to_string());
+
+    assert_eq!(interpreted_result, compiled_result, \"exit status\");
+    assert_eq!(interpreted.stack, compiled.stack, \"stack\");
+    assert_eq!(interpreted.steps, compiled.steps, \"steps\");
+    assert_eq!(interpreted.io.prompts, compiled.io.prompts, \"prompts\");
+
+    let expected = String::from_utf8_lossy(interpreted.io.output.get_ref());
+    let actual = String::from_utf8_lossy(compiled.io.output.get_ref());
+    assert!(expected == actual, \"{}\", colored_diff::PrettyDifference {
+        expected: &expected,
+        actual: &actual,
+    });
+}
+";
+
+// Checks that compiled code reports the same steps to a tracer as the interpreter does.
+const TRACE_TEMPLATE: &str = "
+#[test]
+fn test_%PREFIX%_trace() {
+    let trace = |interpret| {
+        let buffer = SharedBuffer::default();
+        let mut jit = jit_%PREFIX%(interpret);
+        jit.set_tracer(Some(trace::Tracer::new(buffer.clone(), trace::TraceLevel::Instruction)));
+        let _ = jit.run();
+
+        let trace = String::from_utf8(std::mem::take(&mut *buffer.0.lock().unwrap())).unwrap();
+        assert_eq!(jit.steps as usize, trace.lines().count(), \"events\");
+        trace
+    };
+
+    let expected = trace(true);
+    let actual = trace(false);
+    assert!(expected == actual, \"{}\", colored_diff::PrettyDifference {
+        expected: &expected,
+        actual: &actual,
+    });
+}
+";
+
+// Checks that compiled code counts the same executions of each cell as the interpreter does.
+const PROFILE_TEMPLATE: &str = "
+#[test]
+fn test_%PREFIX%_profile() {
+    let profile = |interpret| {
+        let mut jit = jit_%PREFIX%(interpret);
+        jit.options.profile = true;
+        let _ = jit.run();
+
+        let steps = jit.steps;
+        let profile = jit.profile().unwrap();
+        let mut counts = Vec::new();
+        for y in 0..space::HEIGHT {
+            for x in 0..space::WIDTH {
+                counts.push(profile.count(space::Pos::new(x as isize, y as isize)));
+            }
+        }
+        assert_eq!(steps, counts.iter().sum::<u64>(), \"executions\");
+        counts
+    };
+
+    assert_eq!(profile(true), profile(false));
 }
 ";
 
+// Tests run with 64-bit cells, unless a `.bf.cells` file names a different width.
+fn cell_type(path: &Path) -> Result<&'static str, anyhow::Error> {
+    if !path.exists() {
+        return Ok("i64");
+    }
+
+    match std::fs::read_to_string(path)?.trim() {
+        "8" => Ok("cell::Char"),
+        "32" => Ok("i32"),
+        "64" => Ok("i64"),
+        "big" => Ok("num_bigint::BigInt"),
+        other => Err(anyhow::anyhow!("Unknown cell width `{}` in {}", other, path.display())),
+    }
+}
+
 fn main() -> Result<(), anyhow::Error> {
     let out_dir = env::var("OUT_DIR")?;
     let manifest_dir = env::var("CARGO_MANIFEST_DIR")?;
@@ -103,11 +265,22 @@ fn main() -> Result<(), anyhow::Error> {
         let exp = exp?.path().canonicalize()?;
         let fname = exp.file_name().unwrap().to_string_lossy();
         if let Some(prefix) = fname.strip_suffix(".bf") {
+            let cell = cell_type(&exp.with_extension("bf.cells"))?;
             let test = TEST_TEMPLATE
                 .replace("%FILE%", &fname)
                 .replace("%PREFIX%", prefix)
-                .replace("%ROOT%", &manifest_dir);
+                .replace("%ROOT%", &manifest_dir)
+                .replace("%CELL%", cell);
             writeln!(test_file, "{}", test)?;
+
+            if !exp.with_extension("bf.max-code-size").exists() {
+                let test = DIFFERENTIAL_TEMPLATE.replace("%PREFIX%", prefix);
+                writeln!(test_file, "{}", test)?;
+                let test = TRACE_TEMPLATE.replace("%PREFIX%", prefix);
+                writeln!(test_file, "{}", test)?;
+                let test = PROFILE_TEMPLATE.replace("%PREFIX%", prefix);
+                writeln!(test_file, "{}", test)?;
+            }
         }
     }
 
@@ -0,0 +1,231 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use super::cancel::CancelToken;
+use super::cell::{Cell, Overflow};
+use super::dump::BlockDumper;
+use super::error::FunjitError;
+use super::gdb::DebugInfo;
+use super::jit::{Dialect, DivisionByZero, ExitStatus, Jit, Options, Stats, StdIO, IO};
+use super::perf::Perf;
+use super::profile::Profile;
+use super::random::Directions;
+use super::space;
+use super::trace::Tracer;
+
+/// Sets up a program to run. Everything but the source is optional: by default programs follow
+/// Befunge-93 with 64-bit cells, use standard input and output, and run without limits.
+pub struct Builder<I: IO = StdIO, C: Cell = i64> {
+    source: String,
+    io: I,
+    options: Options,
+    directions: Option<Box<dyn Directions + Send>>,
+    cancel: Option<CancelToken>,
+    tracer: Option<Tracer>,
+    dumper: Option<BlockDumper>,
+    perf: Option<Perf>,
+    debug_info: Option<DebugInfo>,
+    cells: PhantomData<C>,
+}
+
+/// How a program run by `Builder::run` finished, along with what it left behind.
+pub struct RunResult<I: IO, C: Cell = i64> {
+    pub result: Result<ExitStatus, FunjitError>,
+    pub io: I,
+    pub stack: Vec<C>,
+    pub steps: u64,
+    pub stats: Stats,
+    /// How often each cell and trace was executed, if the program was profiled.
+    pub profile: Option<Profile>,
+}
+
+impl Builder {
+    pub fn new(source: impl Into<String>) -> Self {
+        Builder {
+            source: source.into(),
+            io: StdIO::new(),
+            options: Options::default(),
+            directions: None,
+            cancel: None,
+            tracer: None,
+            dumper: None,
+            perf: None,
+            debug_info: None,
+            cells: PhantomData,
+        }
+    }
+}
+
+impl<I: IO, C: Cell> Builder<I, C> {
+    /// Run the program with `io` instead of standard input and output.
+    pub fn io<J: IO>(self, io: J) -> Builder<J, C> {
+        Builder {
+            source: self.source,
+            io,
+            options: self.options,
+            directions: self.directions,
+            cancel: self.cancel,
+            tracer: self.tracer,
+            dumper: self.dumper,
+            perf: self.perf,
+            debug_info: self.debug_info,
+            cells: PhantomData,
+        }
+    }
+
+    /// Use `D` for the stack and funge space cells.
+    pub fn cells<D: Cell>(self) -> Builder<I, D> {
+        Builder {
+            source: self.source,
+            io: self.io,
+            options: self.options,
+            directions: self.directions,
+            cancel: self.cancel,
+            tracer: self.tracer,
+            dumper: self.dumper,
+            perf: self.perf,
+            debug_info: self.debug_info,
+            cells: PhantomData,
+        }
+    }
+
+    /// Replace every option at once.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.options.dialect = dialect;
+        self
+    }
+
+    pub fn division_by_zero(mut self, behavior: DivisionByZero) -> Self {
+        self.options.division_by_zero = Some(behavior);
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.options.overflow = overflow;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    /// Take the directions for `?` from `directions`, rather than a random number generator.
+    pub fn directions(mut self, directions: Box<dyn Directions + Send>) -> Self {
+        self.directions = Some(directions);
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.options.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn max_stack(mut self, max_stack: usize) -> Self {
+        self.options.max_stack = Some(max_stack);
+        self
+    }
+
+    pub fn max_code_size(mut self, max_code_size: usize) -> Self {
+        self.options.max_code_size = Some(max_code_size);
+        self
+    }
+
+    /// Interpret the program a step at a time, rather than compiling it.
+    pub fn interpret(mut self, interpret: bool) -> Self {
+        self.options.interpret = interpret;
+        self
+    }
+
+    pub fn compile_threshold(mut self, threshold: u32) -> Self {
+        self.options.compile_threshold = threshold;
+        self
+    }
+
+    /// Count how often each cell and trace is executed, for `RunResult::profile`.
+    pub fn profile(mut self, profile: bool) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Record what the program does with `tracer` as it runs.
+    pub fn trace(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    /// Write out each block as it's compiled with `dumper`.
+    pub fn dump_blocks(mut self, dumper: BlockDumper) -> Self {
+        self.dumper = Some(dumper);
+        self
+    }
+
+    /// Tell `perf` about each block as it's compiled.
+    pub fn perf(mut self, perf: Perf) -> Self {
+        self.perf = Some(perf);
+        self
+    }
+
+    /// Describe each block to GDB as it's compiled.
+    pub fn debug_info(mut self, debug_info: DebugInfo) -> Self {
+        self.debug_info = Some(debug_info);
+        self
+    }
+
+    /// Set up the `Jit` without running it.
+    pub fn build(self) -> Jit<I, C> {
+        let cells = space::Funge93::from_string(&self.source);
+        let mut jit = Jit::with_options(cells, self.io, self.options);
+        if let Some(directions) = self.directions {
+            jit.set_directions(directions);
+        }
+        if let Some(token) = self.cancel {
+            jit.set_cancel_token(token);
+        }
+        if self.tracer.is_some() {
+            jit.set_tracer(self.tracer);
+        }
+        if self.dumper.is_some() {
+            jit.set_dumper(self.dumper);
+        }
+        if self.perf.is_some() {
+            jit.set_perf(self.perf);
+        }
+        if self.debug_info.is_some() {
+            jit.set_debug_info(self.debug_info);
+        }
+
+        jit
+    }
+
+    /// Run the program to completion.
+    pub fn run(self) -> RunResult<I, C> {
+        let mut jit = self.build();
+        let result = jit.run();
+        let profile = jit.profile().cloned();
+
+        RunResult {
+            result,
+            io: jit.io,
+            stack: jit.stack,
+            steps: jit.steps,
+            stats: jit.stats,
+            profile,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Stops a running program from another thread. Clones share the same flag, so a clone can be
+/// handed to a supervisor while the original is given to `Jit::set_cancel_token`.
+///
+/// Compiled code checks the flag each time it enters a block, so cancellation takes effect at
+/// the next block boundary, including the back-edge of a loop.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the program to stop. This can't be undone, so a cancelled token stops every later run
+    /// that uses it straight away.
+    pub fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+
+    /// The flag that compiled code reads directly. It's only written through `cancel`, so a plain
+    /// byte load is enough to see it.
+    pub fn flag(&self) -> &AtomicBool {
+        &self.flag
+    }
+}
+
+#[test]
+fn test_cancel_loop() {
+    use super::{jit, space};
+
+    let token = CancelToken::new();
+    let handle = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            let cells = space::Funge93::<i64>::from_string(">v\n^<");
+            let mut jit = jit::Jit::new(cells, jit::StdIO::new());
+            jit.set_cancel_token(token);
+            jit.run().unwrap()
+        })
+    };
+
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    token.cancel();
+    assert_eq!(jit::ExitStatus::Cancelled, handle.join().unwrap());
+}
@@ -0,0 +1,211 @@
+use num_bigint::{BigInt, Sign};
+use num_traits::{ToPrimitive, Zero};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// How compiled code represents the values of a cell type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Width {
+    /// Values are kept sign-extended in 64-bit registers, and narrowed to the given number of bits
+    /// after every arithmetic operation.
+    Native(u32),
+
+    /// Values live on the heap, so compiled code calls back into the `Jit` for every operation
+    /// that inspects them.
+    Boxed,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// What happens when the result of an arithmetic operation doesn't fit in a cell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    Wrap,
+    Saturate,
+    /// Stop the program with an error.
+    Trap,
+}
+
+impl std::str::FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap" => Ok(Overflow::Wrap),
+            "saturate" => Ok(Overflow::Saturate),
+            "trap" => Ok(Overflow::Trap),
+            _ => Err(format!("Unknown overflow behavior: {}", s)),
+        }
+    }
+}
+
+/// The values held on the stack and in funge space. They can be serialized, so that a program's
+/// state can be saved.
+pub trait Cell:
+    Clone + Default + PartialEq + PartialOrd + fmt::Debug + fmt::Display
+    + Serialize + DeserializeOwned + 'static
+{
+    const WIDTH: Width;
+    /// The name of the type, as `--cells` takes it.
+    const NAME: &'static str;
+
+    fn from_isize(val: isize) -> Self;
+
+    /// Convert to an `isize`, keeping only the low 64 bits of values that don't fit.
+    fn to_isize(&self) -> isize;
+
+    /// The instruction this value represents when executed from funge space. Values outside of
+    /// the byte range map to `0`, which is not a valid instruction.
+    fn to_instr(&self) -> u8;
+
+    fn is_zero(&self) -> bool;
+
+    /// The value that's kept when this is stored in funge space.
+    fn stored(self) -> Self {
+        self
+    }
+
+    /// Apply an arithmetic operation, returning `None` if the result overflows and `overflow` is
+    /// `Overflow::Trap`. Division truncates, and the divisor of `Div` and `Rem` must be non-zero.
+    fn arith(&self, op: Arith, other: &Self, overflow: Overflow) -> Option<Self>;
+}
+
+macro_rules! native_cell {
+    ($t:ty, $bits:expr) => {
+        impl Cell for $t {
+            const WIDTH: Width = Width::Native($bits);
+            const NAME: &'static str = stringify!($bits);
+
+            fn from_isize(val: isize) -> Self {
+                val as $t
+            }
+
+            fn to_isize(&self) -> isize {
+                *self as isize
+            }
+
+            fn to_instr(&self) -> u8 {
+                u8::try_from(*self).unwrap_or(0)
+            }
+
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+
+            fn arith(&self, op: Arith, other: &Self, overflow: Overflow) -> Option<Self> {
+                let (a, b) = (*self, *other);
+                match (op, overflow) {
+                    // MIN % -1 is zero, which never overflows
+                    (Arith::Rem, _) => Some(a.wrapping_rem(b)),
+
+                    (Arith::Add, Overflow::Wrap) => Some(a.wrapping_add(b)),
+                    (Arith::Sub, Overflow::Wrap) => Some(a.wrapping_sub(b)),
+                    (Arith::Mul, Overflow::Wrap) => Some(a.wrapping_mul(b)),
+                    (Arith::Div, Overflow::Wrap) => Some(a.wrapping_div(b)),
+
+                    (Arith::Add, Overflow::Saturate) => Some(a.saturating_add(b)),
+                    (Arith::Sub, Overflow::Saturate) => Some(a.saturating_sub(b)),
+                    (Arith::Mul, Overflow::Saturate) => Some(a.saturating_mul(b)),
+                    (Arith::Div, Overflow::Saturate) => Some(a.saturating_div(b)),
+
+                    (Arith::Add, Overflow::Trap) => a.checked_add(b),
+                    (Arith::Sub, Overflow::Trap) => a.checked_sub(b),
+                    (Arith::Mul, Overflow::Trap) => a.checked_mul(b),
+                    (Arith::Div, Overflow::Trap) => a.checked_div(b),
+                }
+            }
+        }
+    };
+}
+
+native_cell!(i32, 32);
+native_cell!(i64, 64);
+
+/// The cells of strict Befunge-93, which follows the reference implementation in keeping `long`
+/// values on the stack, and storing them in funge space as `char`s. Values are narrowed to a signed
+/// byte as they're stored, so every value in funge space is also an instruction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Char(pub i64);
+
+impl fmt::Display for Char {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Cell for Char {
+    const WIDTH: Width = Width::Native(64);
+    const NAME: &'static str = "8";
+
+    fn from_isize(val: isize) -> Self {
+        Char(val as i64)
+    }
+
+    fn to_isize(&self) -> isize {
+        self.0 as isize
+    }
+
+    fn to_instr(&self) -> u8 {
+        self.0 as u8
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn stored(self) -> Self {
+        Char(self.0 as i8 as i64)
+    }
+
+    fn arith(&self, op: Arith, other: &Self, overflow: Overflow) -> Option<Self> {
+        self.0.arith(op, &other.0, overflow).map(Char)
+    }
+}
+
+impl Cell for BigInt {
+    const WIDTH: Width = Width::Boxed;
+    const NAME: &'static str = "big";
+
+    fn from_isize(val: isize) -> Self {
+        BigInt::from(val)
+    }
+
+    fn to_isize(&self) -> isize {
+        let (sign, digits) = self.to_u64_digits();
+        let low = digits.first().copied().unwrap_or(0) as isize;
+        if sign == Sign::Minus {
+            low.wrapping_neg()
+        } else {
+            low
+        }
+    }
+
+    fn to_instr(&self) -> u8 {
+        self.to_u8().unwrap_or(0)
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+
+    // Big integers never overflow.
+    fn arith(&self, op: Arith, other: &Self, _overflow: Overflow) -> Option<Self> {
+        Some(match op {
+            Arith::Add => self + other,
+            Arith::Sub => self - other,
+            Arith::Mul => self * other,
+            Arith::Div => self / other,
+            Arith::Rem => self % other,
+        })
+    }
+}
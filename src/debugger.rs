@@ -0,0 +1,229 @@
+use std::io::{self, Write};
+
+use super::cell::Cell;
+use super::error::FunjitError;
+use super::history::History;
+use super::jit::{Budget, ExitStatus, Jit, Watches, IO};
+use super::space;
+
+const HELP: &str = "\
+step [N]          execute the next N instructions, or just the next one
+next              run to the end of the current trace
+continue          run until the program stops, or reaches a breakpoint or watchpoint
+back [N]          go back N instructions, or just one
+goto N            go to where the program had executed N instructions
+break X Y         stop before executing the cell at (X, Y)
+delete X Y        remove the breakpoint at (X, Y)
+watch X Y         stop after the program writes to the cell at (X, Y)
+watch stack N     stop when the stack grows to N values
+unwatch X Y       stop watching the cell at (X, Y)
+unwatch stack     stop watching the stack depth
+stack             print the stack, from the bottom up
+pc                print the position and direction of the IP
+view [R]          print the playfield within R rows of the IP
+help              print this message
+quit              stop debugging";
+
+/// Runs the commands typed into `funjit debug` against a program. Stepping interprets the program
+/// a cell at a time, while continuing runs compiled code for any blocks without breakpoints. The
+/// program is recorded as it runs, so that it can be taken back to an earlier step.
+pub struct Debugger<I: IO, C: Cell> {
+    pub jit: Jit<I, C>,
+    history: History<C>,
+    finished: bool,
+}
+
+impl<I: IO, C: Cell> Debugger<I, C> {
+    pub fn new(mut jit: Jit<I, C>) -> Self {
+        Debugger {
+            history: History::new(&mut jit),
+            jit,
+            finished: false,
+        }
+    }
+
+    /// Run a single command, writing what it shows to `out`. Returns false once the user has
+    /// asked to quit.
+    pub fn command(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Option<Vec<isize>> = words.iter().skip(1).map(|w| w.parse().ok()).collect();
+
+        match (words.first().copied(), numbers.as_deref()) {
+            (None, _) => (),
+            (Some("quit") | Some("q"), _) => return Ok(false),
+            (Some("help") | Some("h"), _) => writeln!(out, "{}", HELP)?,
+
+            (Some("step") | Some("s"), Some([])) => self.execute(Budget::Steps(1), out)?,
+            (Some("step") | Some("s"), Some(&[n])) if n > 0 => {
+                self.execute(Budget::Steps(n as u64), out)?
+            }
+            (Some("next") | Some("n"), Some([])) => self.execute(Budget::Traces(1), out)?,
+            (Some("continue") | Some("c"), Some([])) => self.resume(None, out)?,
+            (Some("back"), Some([])) => self.back(1, out)?,
+            (Some("back"), Some(&[n])) if n > 0 => self.back(n as u64, out)?,
+            (Some("goto"), Some(&[n])) if n >= 0 => self.seek(n as u64, out)?,
+
+            (Some("break") | Some("b"), Some(&[x, y])) => {
+                let pos = Self::pos(x, y, out)?;
+                self.change_watches(|watches| watches.breakpoints.insert(pos));
+            }
+            (Some("delete") | Some("d"), Some(&[x, y])) => {
+                let pos = Self::pos(x, y, out)?;
+                if !self.change_watches(|watches| watches.breakpoints.remove(&pos)) {
+                    writeln!(out, "No breakpoint at ({}, {})", x, y)?;
+                }
+            }
+            (Some("watch") | Some("w"), Some(&[x, y])) => {
+                let pos = Self::pos(x, y, out)?;
+                self.change_watches(|watches| watches.cells.insert(pos));
+            }
+            (Some("unwatch"), Some(&[x, y])) => {
+                let pos = Self::pos(x, y, out)?;
+                if !self.change_watches(|watches| watches.cells.remove(&pos)) {
+                    writeln!(out, "({}, {}) isn't being watched", x, y)?;
+                }
+            }
+            (Some("watch") | Some("w"), None) if words.len() == 3 && words[1] == "stack" => {
+                match words[2].parse() {
+                    Ok(depth) => {
+                        self.change_watches(|watches| watches.stack_depth.replace(depth));
+                    }
+                    Err(_) => writeln!(out, "Not a stack depth: {}", words[2])?,
+                }
+            }
+            (Some("unwatch"), None) if words[1..] == ["stack"] => {
+                self.change_watches(|watches| watches.stack_depth.take());
+            }
+
+            (Some("stack"), Some([])) => {
+                let stack: Vec<String> = self.jit.stack.iter().map(C::to_string).collect();
+                writeln!(out, "[{}]", stack.join(", "))?;
+            }
+            (Some("pc"), Some([])) => self.where_is(out)?,
+            (Some("view") | Some("v"), Some([])) => self.view(2, out)?,
+            (Some("view") | Some("v"), Some(&[radius])) if radius >= 0 => {
+                self.view(radius, out)?
+            }
+
+            _ => writeln!(out, "Unknown command: {} (try `help`)", line.trim())?,
+        }
+
+        Ok(true)
+    }
+
+    fn pos(x: isize, y: isize, out: &mut dyn Write) -> io::Result<space::Pos> {
+        let in_bounds = (0..space::WIDTH as isize).contains(&x)
+            && (0..space::HEIGHT as isize).contains(&y);
+        if !in_bounds {
+            writeln!(out, "({}, {}) is outside the playfield, and will never be reached", x, y)?;
+        }
+        Ok(space::Pos::new(x, y))
+    }
+
+    fn change_watches<T>(&mut self, change: impl FnOnce(&mut Watches) -> T) -> T {
+        let mut watches = self.jit.watches().clone();
+        let result = change(&mut watches);
+        self.jit.set_watches(watches);
+        result
+    }
+
+    fn execute(&mut self, budget: Budget, out: &mut dyn Write) -> io::Result<()> {
+        self.resume(Some(budget), out)
+    }
+
+    fn resume(&mut self, budget: Option<Budget>, out: &mut dyn Write) -> io::Result<()> {
+        if self.finished {
+            return writeln!(out, "The program has finished");
+        }
+
+        let result = self.history.run(&mut self.jit, budget);
+        self.report(result, out)
+    }
+
+    fn back(&mut self, steps: u64, out: &mut dyn Write) -> io::Result<()> {
+        if self.jit.steps == 0 {
+            return writeln!(out, "Already at the start of the program");
+        }
+        self.seek(self.jit.steps.saturating_sub(steps), out)
+    }
+
+    fn seek(&mut self, steps: u64, out: &mut dyn Write) -> io::Result<()> {
+        if steps < self.jit.steps {
+            self.finished = false;
+        } else if self.finished {
+            return writeln!(out, "The program has finished");
+        }
+
+        let result = self.history.seek(&mut self.jit, steps);
+        self.report(result, out)
+    }
+
+    fn report(
+        &mut self,
+        result: Result<ExitStatus, FunjitError>,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        match result {
+            Ok(ExitStatus::Paused) => (),
+            Ok(ExitStatus::Breakpoint) => write!(out, "Breakpoint: ")?,
+            Ok(ExitStatus::Watchpoint) => write!(out, "Watchpoint: ")?,
+            Ok(ExitStatus::Terminated) => {
+                self.finished = true;
+                return writeln!(out, "Terminated after {} steps", self.jit.steps);
+            }
+            Ok(status) => {
+                self.finished = true;
+                return writeln!(out, "Stopped after {} steps: {:?}", self.jit.steps, status);
+            }
+            Err(err) => {
+                self.finished = true;
+                return writeln!(out, "Error after {} steps: {}", self.jit.steps, err);
+            }
+        }
+
+        self.where_is(out)
+    }
+
+    fn where_is(&self, out: &mut dyn Write) -> io::Result<()> {
+        let (pc, delta) = (self.jit.pc, self.jit.delta);
+        let instr = self.jit.cells.instr(pc.x as usize, pc.y as usize);
+        writeln!(
+            out,
+            "pc ({}, {}) delta ({}, {}) at '{}'",
+            pc.x,
+            pc.y,
+            delta.x,
+            delta.y,
+            printable(instr),
+        )
+    }
+
+    // Print the rows within `radius` of the IP, marking its row and column.
+    fn view(&self, radius: isize, out: &mut dyn Write) -> io::Result<()> {
+        let pc = self.jit.pc;
+        let height = space::HEIGHT as isize;
+        let radius = radius.min((height - 1) / 2);
+
+        for dy in -radius..=radius {
+            let y = (pc.y + dy).rem_euclid(height);
+            let row: String = (0..space::WIDTH)
+                .map(|x| printable(self.jit.cells.instr(x, y as usize)))
+                .collect();
+            let marker = if dy == 0 { '>' } else { ' ' };
+            writeln!(out, "{}{:3} |{}", marker, y, row.trim_end())?;
+            if dy == 0 {
+                writeln!(out, "      {}^", " ".repeat(pc.x as usize))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn printable(instr: u8) -> char {
+    if instr.is_ascii_graphic() || instr == b' ' {
+        instr as char
+    } else {
+        '\u{b7}'
+    }
+}
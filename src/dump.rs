@@ -0,0 +1,115 @@
+use std::io::{self, Write};
+
+use iced_x86::{Code, Decoder, DecoderOptions, Formatter, IntelFormatter};
+
+use super::cell::Cell;
+use super::jit::{Block, CompiledBlock, Jit, IO};
+
+/// Writes out each block as it's compiled: where it starts, the instructions it was traced from,
+/// how it ends, and a disassembly of the machine code it was compiled to.
+///
+/// ```text
+/// block at (0, 0) moving (1, 0)
+///   instructions: 12+.@
+///   steps: 5, loops: false, mutates: false, terminates: true, random: false
+///   118 bytes at 0x7f5e3c9a4000:
+///     0000  55                    push rbp
+///     ...
+///     0021  48b8f0d1a23c5e7f0000  mov rax,7F5E3CA2D1F0h  ; Jit::push
+/// ```
+///
+/// Calls out of compiled code load the address of the `Jit` method they call into a register,
+/// which is named in a comment.
+pub struct BlockDumper {
+    out: Box<dyn Write + Send>,
+}
+
+impl BlockDumper {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        BlockDumper { out: Box::new(out) }
+    }
+
+    pub fn dump<I: IO, C: Cell>(
+        &mut self,
+        block: &Block,
+        compiled: &CompiledBlock<I, C>,
+    ) -> io::Result<()> {
+        let (start, delta) = block.entry();
+        writeln!(
+            self.out,
+            "block at ({}, {}) moving ({}, {})",
+            start.x, start.y, delta.x, delta.y
+        )?;
+        writeln!(self.out, "  instructions: {}", block.code)?;
+        writeln!(
+            self.out,
+            "  steps: {}, loops: {}, mutates: {}, terminates: {}, random: {}",
+            block.steps, block.loops, block.mutates, block.terminates, block.random
+        )?;
+
+        let code = compiled.machine_code();
+        let addr = compiled.addr() as u64;
+        writeln!(self.out, "  {} bytes at {:#x}:", code.len(), addr)?;
+
+        let functions = functions::<I, C>();
+        // addresses are given as offsets into the block, so that branches can be followed
+        let mut decoder = Decoder::with_ip(64, code, 0, DecoderOptions::NONE);
+        let mut formatter = IntelFormatter::new();
+        formatter.options_mut().set_branch_leading_zeros(false);
+        let mut text = String::new();
+        for instruction in &mut decoder {
+            text.clear();
+            formatter.format(&instruction, &mut text);
+
+            let offset = instruction.ip() as usize;
+            let bytes: String = code[offset..offset + instruction.len()]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            write!(self.out, "    {:04x}  {:<20}  {}", offset, bytes, text)?;
+
+            if instruction.code() == Code::Mov_r64_imm64 {
+                let target = instruction.immediate64() as *const ();
+                if let Some((name, _)) = functions.iter().find(|(_, addr)| *addr == target) {
+                    write!(self.out, "  ; Jit::{}", name)?;
+                }
+            }
+            writeln!(self.out)?;
+        }
+
+        writeln!(self.out)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// The `Jit` methods that compiled code calls, with their addresses.
+fn functions<I: IO, C: Cell>() -> Vec<(&'static str, *const ())> {
+    vec![
+        ("arith", Jit::<I, C>::arith as *const ()),
+        ("arith_slow", Jit::<I, C>::arith_slow as *const ()),
+        ("check_budget", Jit::<I, C>::check_budget as *const ()),
+        ("divide_by_zero", Jit::<I, C>::divide_by_zero as *const ()),
+        ("dup", Jit::<I, C>::dup as *const ()),
+        ("get", Jit::<I, C>::get as *const ()),
+        ("greater", Jit::<I, C>::greater as *const ()),
+        ("input", Jit::<I, C>::input as *const ()),
+        ("input_number", Jit::<I, C>::input_number as *const ()),
+        ("leave_block", Jit::<I, C>::leave_block as *const ()),
+        ("not", Jit::<I, C>::not as *const ()),
+        ("output", Jit::<I, C>::output as *const ()),
+        ("output_number", Jit::<I, C>::output_number as *const ()),
+        ("peek", Jit::<I, C>::peek as *const ()),
+        ("pop", Jit::<I, C>::pop as *const ()),
+        ("push", Jit::<I, C>::push as *const ()),
+        ("random_direction", Jit::<I, C>::random_direction as *const ()),
+        ("set_delta", Jit::<I, C>::set_delta as *const ()),
+        ("set_pc", Jit::<I, C>::set_pc as *const ()),
+        ("swap", Jit::<I, C>::swap as *const ()),
+        ("trace_block", Jit::<I, C>::trace_block as *const ()),
+        ("trace_step", Jit::<I, C>::trace_step as *const ()),
+        ("turn", Jit::<I, C>::turn as *const ()),
+    ]
+}
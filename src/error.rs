@@ -0,0 +1,53 @@
+use std::fmt;
+use std::io;
+
+use super::space;
+
+/// Resources that a program is limited in how much of it uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    StackDepth(usize),
+    CodeSize(usize),
+}
+
+/// Errors that stop a running program.
+#[derive(Debug)]
+pub enum FunjitError {
+    DivisionByZero(space::Pos),
+    Overflow(space::Pos),
+    Io(space::Pos, io::Error),
+    LimitExceeded(Limit),
+    /// Writing to a `trace::Tracer` failed.
+    Trace(io::Error),
+    /// Writing to a `dump::BlockDumper` failed.
+    Dump(io::Error),
+    /// Writing a block to the `perf::Perf` files failed.
+    Perf(io::Error),
+}
+
+impl fmt::Display for FunjitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FunjitError::DivisionByZero(pos) => {
+                write!(f, "Division by zero at ({}, {})", pos.x, pos.y)
+            }
+            FunjitError::Overflow(pos) => {
+                write!(f, "Arithmetic overflow at ({}, {})", pos.x, pos.y)
+            }
+            FunjitError::Io(pos, err) => {
+                write!(f, "IO error at ({}, {}): {}", pos.x, pos.y, err)
+            }
+            FunjitError::LimitExceeded(Limit::StackDepth(max)) => {
+                write!(f, "Stack depth limit of {} exceeded", max)
+            }
+            FunjitError::LimitExceeded(Limit::CodeSize(max)) => {
+                write!(f, "Compiled code size limit of {} bytes exceeded", max)
+            }
+            FunjitError::Trace(err) => write!(f, "Failed to write the trace: {}", err),
+            FunjitError::Dump(err) => write!(f, "Failed to dump a compiled block: {}", err),
+            FunjitError::Perf(err) => write!(f, "Failed to write the perf map or jitdump: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for FunjitError {}
@@ -0,0 +1,356 @@
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use gimli::write::{
+    Address, AttributeValue, CallFrameInstruction, CommonInformationEntry, DebugFrame, DwarfUnit,
+    EndianVec, FrameDescriptionEntry, FrameTable, LineProgram, LineString, Sections,
+};
+use gimli::{Encoding, Format, LineEncoding, LittleEndian, X86_64};
+use object::elf;
+use object::write::elf::{FileHeader, SectionHeader, Sym, Writer};
+use object::Endianness;
+
+use super::cell::Cell;
+use super::jit::{Block, CompiledBlock, IO};
+
+// GDB's JIT compilation interface, as described in "JIT Compilation Interface" in the GDB manual.
+// GDB puts a breakpoint on `__jit_debug_register_code`, and reads the object file that
+// `__jit_debug_descriptor` points it to whenever it's called.
+
+#[repr(C)]
+struct JitCodeEntry {
+    next_entry: *mut JitCodeEntry,
+    prev_entry: *mut JitCodeEntry,
+    symfile_addr: *const u8,
+    symfile_size: u64,
+}
+
+#[repr(C)]
+pub struct JitDescriptor {
+    version: u32,
+    action_flag: u32,
+    relevant_entry: *mut JitCodeEntry,
+    first_entry: *mut JitCodeEntry,
+}
+
+const JIT_REGISTER_FN: u32 = 1;
+const JIT_UNREGISTER_FN: u32 = 2;
+
+#[no_mangle]
+pub static mut __jit_debug_descriptor: JitDescriptor = JitDescriptor {
+    version: 1,
+    action_flag: 0,
+    relevant_entry: std::ptr::null_mut(),
+    first_entry: std::ptr::null_mut(),
+};
+
+#[no_mangle]
+#[inline(never)]
+pub extern "C" fn __jit_debug_register_code() {
+    // keep the call from being optimized away, as GDB needs to stop here
+    std::hint::black_box(());
+}
+
+// Held while the descriptor is changed, as programs may be compiled on several threads at once.
+static DESCRIPTOR_LOCK: Mutex<()> = Mutex::new(());
+
+/// Describes each block to GDB as it's compiled, so that GDB can name the block in backtraces,
+/// unwind through it, and show the line of the Befunge `source` each instruction was compiled
+/// from. Lines and columns count from 1, so the cell at (x, y) is at line y + 1, column x + 1.
+pub struct DebugInfo {
+    source: PathBuf,
+}
+
+impl DebugInfo {
+    /// Describe blocks as compiled from the program in `source`, which GDB reads to show them.
+    pub fn new(source: impl Into<PathBuf>) -> Self {
+        DebugInfo {
+            source: source.into(),
+        }
+    }
+
+    /// Tell GDB about a block that's just been compiled, until the `Registration` is dropped.
+    pub fn register<I: IO, C: Cell>(
+        &self,
+        block: &Block,
+        compiled: &CompiledBlock<I, C>,
+    ) -> Registration {
+        let (pos, delta) = block.entry();
+        let name =
+            format!("befunge block at ({}, {}) moving ({}, {})", pos.x, pos.y, delta.x, delta.y);
+        let symfile = self.object(&name, compiled);
+
+        let entry = Box::into_raw(Box::new(JitCodeEntry {
+            next_entry: std::ptr::null_mut(),
+            prev_entry: std::ptr::null_mut(),
+            symfile_addr: symfile.as_ptr(),
+            symfile_size: symfile.len() as u64,
+        }));
+
+        let _lock = DESCRIPTOR_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = std::ptr::addr_of_mut!(__jit_debug_descriptor);
+            (*entry).next_entry = (*descriptor).first_entry;
+            if let Some(next) = (*entry).next_entry.as_mut() {
+                next.prev_entry = entry;
+            }
+            (*descriptor).first_entry = entry;
+            (*descriptor).relevant_entry = entry;
+            (*descriptor).action_flag = JIT_REGISTER_FN;
+            __jit_debug_register_code();
+        }
+
+        Registration { entry, symfile }
+    }
+
+    // An ELF object with the block's symbol, line table and call frame information. The code
+    // itself isn't copied, as the `.text` section gives the address it's already at.
+    fn object<I: IO, C: Cell>(&self, name: &str, compiled: &CompiledBlock<I, C>) -> Vec<u8> {
+        let addr = compiled.addr() as u64;
+        let size = compiled.machine_code().len() as u64;
+        let mut sections = self.dwarf(name, compiled).expect("Invalid debug info for a block");
+
+        let mut debug_frame = DebugFrame::from(EndianVec::new(LittleEndian));
+        frames(addr, size).write_debug_frame(&mut debug_frame).expect("Invalid frame info");
+        sections.debug_frame = debug_frame;
+
+        let mut dwarf = Vec::new();
+        sections
+            .for_each(|id, data| {
+                if !data.slice().is_empty() {
+                    dwarf.push((id.name(), data.slice().to_vec()));
+                }
+                Ok::<_, gimli::write::Error>(())
+            })
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        let mut writer = Writer::new(Endianness::Little, true, &mut buffer);
+        writer.reserve_file_header();
+
+        writer.reserve_null_section_index();
+        let text_name = writer.add_section_name(b".text");
+        let text = writer.reserve_section_index();
+        let dwarf: Vec<_> = dwarf
+            .iter()
+            .map(|(name, data)| {
+                let name = writer.add_section_name(name.as_bytes());
+                writer.reserve_section_index();
+                (name, data)
+            })
+            .collect();
+        writer.reserve_symtab_section_index();
+        writer.reserve_strtab_section_index();
+        writer.reserve_shstrtab_section_index();
+
+        writer.reserve_null_symbol_index();
+        let symbol_name = writer.add_string(name.as_bytes());
+        writer.reserve_symbol_index(Some(text));
+
+        let offsets: Vec<_> =
+            dwarf.iter().map(|(_, data)| writer.reserve(data.len(), 1)).collect();
+        writer.reserve_symtab();
+        writer.reserve_strtab();
+        writer.reserve_shstrtab();
+        writer.reserve_section_headers();
+
+        writer
+            .write_file_header(&FileHeader {
+                os_abi: elf::ELFOSABI_NONE,
+                abi_version: 0,
+                e_type: elf::ET_REL,
+                e_machine: elf::EM_X86_64,
+                e_entry: 0,
+                e_flags: 0,
+            })
+            .unwrap();
+        for (_, data) in &dwarf {
+            writer.write(data);
+        }
+        writer.write_null_symbol();
+        writer.write_symbol(&Sym {
+            name: Some(symbol_name),
+            section: Some(text),
+            st_info: (elf::STB_GLOBAL << 4) | elf::STT_FUNC,
+            st_other: elf::STV_DEFAULT,
+            st_shndx: 0,
+            st_value: addr,
+            st_size: size,
+        });
+        writer.write_strtab();
+        writer.write_shstrtab();
+
+        writer.write_null_section_header();
+        writer.write_section_header(&SectionHeader {
+            name: Some(text_name),
+            sh_type: elf::SHT_NOBITS,
+            sh_flags: (elf::SHF_ALLOC | elf::SHF_EXECINSTR).into(),
+            sh_addr: addr,
+            sh_offset: 0,
+            sh_size: size,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 16,
+            sh_entsize: 0,
+        });
+        for ((name, data), offset) in dwarf.iter().zip(offsets) {
+            writer.write_section_header(&SectionHeader {
+                name: Some(*name),
+                sh_type: elf::SHT_PROGBITS,
+                sh_flags: 0,
+                sh_addr: 0,
+                sh_offset: offset as u64,
+                sh_size: data.len() as u64,
+                sh_link: 0,
+                sh_info: 0,
+                sh_addralign: 1,
+                sh_entsize: 0,
+            });
+        }
+        writer.write_symtab_section_header(1);
+        writer.write_strtab_section_header();
+        writer.write_shstrtab_section_header();
+
+        buffer
+    }
+
+    // A compilation unit for the block, with a function covering all its code and a line for each
+    // instruction.
+    fn dwarf<I: IO, C: Cell>(
+        &self,
+        name: &str,
+        compiled: &CompiledBlock<I, C>,
+    ) -> gimli::write::Result<Sections<EndianVec<LittleEndian>>> {
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let addr = Address::Constant(compiled.addr() as u64);
+        let size = compiled.machine_code().len() as u64;
+
+        let dir = self.source.parent().map_or_else(|| ".".into(), |dir| dir.to_string_lossy());
+        let dir = if dir.is_empty() { ".".into() } else { dir };
+        let file = self.source.file_name().map_or_else(
+            || self.source.to_string_lossy(),
+            |file| file.to_string_lossy(),
+        );
+
+        let mut dwarf = DwarfUnit::new(encoding);
+        let mut lines = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            LineString::String(dir.as_bytes().to_vec()),
+            LineString::String(file.as_bytes().to_vec()),
+            None,
+        );
+        let dir_id = lines.default_directory();
+        let file_id = lines.add_file(LineString::String(file.as_bytes().to_vec()), dir_id, None);
+        lines.begin_sequence(Some(addr));
+        for &(offset, pos) in compiled.lines() {
+            let row = lines.row();
+            row.address_offset = offset as u64;
+            row.file = file_id;
+            row.line = pos.y as u64 + 1;
+            row.column = pos.x as u64 + 1;
+            lines.generate_row();
+        }
+        lines.end_sequence(size);
+        dwarf.unit.line_program = lines;
+
+        let root = dwarf.unit.root();
+        let unit = dwarf.unit.get_mut(root);
+        unit.set(gimli::DW_AT_producer, AttributeValue::String(b"funjit".to_vec()));
+        unit.set(gimli::DW_AT_name, AttributeValue::String(file.as_bytes().to_vec()));
+        unit.set(gimli::DW_AT_comp_dir, AttributeValue::String(dir.as_bytes().to_vec()));
+        unit.set(gimli::DW_AT_low_pc, AttributeValue::Address(addr));
+        unit.set(gimli::DW_AT_high_pc, AttributeValue::Udata(size));
+
+        let function = dwarf.unit.add(root, gimli::DW_TAG_subprogram);
+        let function = dwarf.unit.get_mut(function);
+        function.set(gimli::DW_AT_name, AttributeValue::String(name.as_bytes().to_vec()));
+        function.set(gimli::DW_AT_external, AttributeValue::Flag(true));
+        function.set(gimli::DW_AT_low_pc, AttributeValue::Address(addr));
+        function.set(gimli::DW_AT_high_pc, AttributeValue::Udata(size));
+
+        let mut sections = Sections::new(EndianVec::new(LittleEndian));
+        dwarf.write(&mut sections)?;
+        Ok(sections)
+    }
+}
+
+// Unwinding information for the frame that the prologue sets up: `rbp` is pushed, and the
+// canonical frame address is found from it for the rest of the block.
+fn frames(addr: u64, size: u64) -> FrameTable {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 1,
+        address_size: 8,
+    };
+    let mut cie = CommonInformationEntry::new(encoding, 1, -8, X86_64::RA);
+    cie.add_instruction(CallFrameInstruction::Cfa(X86_64::RSP, 8));
+    cie.add_instruction(CallFrameInstruction::Offset(X86_64::RA, -8));
+
+    // `push rbp` is one byte, and `mov rbp, rsp` three more
+    let mut fde = FrameDescriptionEntry::new(Address::Constant(addr), size as u32);
+    fde.add_instruction(1, CallFrameInstruction::CfaOffset(16));
+    fde.add_instruction(1, CallFrameInstruction::Offset(X86_64::RBP, -16));
+    fde.add_instruction(4, CallFrameInstruction::CfaRegister(X86_64::RBP));
+
+    let mut frames = FrameTable::default();
+    let cie = frames.add_cie(cie);
+    frames.add_fde(cie, fde);
+    frames
+}
+
+/// The objects currently registered with GDB, newest first.
+pub fn registered() -> Vec<Vec<u8>> {
+    let _lock = DESCRIPTOR_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let mut symfiles = Vec::new();
+    unsafe {
+        let mut entry = (*std::ptr::addr_of!(__jit_debug_descriptor)).first_entry;
+        while let Some(current) = entry.as_ref() {
+            let size = current.symfile_size as usize;
+            symfiles.push(std::slice::from_raw_parts(current.symfile_addr, size).to_vec());
+            entry = current.next_entry;
+        }
+    }
+    symfiles
+}
+
+/// A block that GDB knows about, which is unregistered when it's dropped.
+pub struct Registration {
+    entry: *mut JitCodeEntry,
+    // the object GDB reads, which has to stay where it is until the entry is unregistered
+    #[allow(dead_code)]
+    symfile: Vec<u8>,
+}
+
+// SAFETY: the entry is only ever read or written with `DESCRIPTOR_LOCK` held, whichever thread
+// registers or drops it, and GDB only reads it while the program is stopped. Nothing else points
+// into the entry or the symfile, so they can be owned by any thread.
+unsafe impl Send for Registration {}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _lock = DESCRIPTOR_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        unsafe {
+            let descriptor = std::ptr::addr_of_mut!(__jit_debug_descriptor);
+            let entry = &mut *self.entry;
+            if let Some(prev) = entry.prev_entry.as_mut() {
+                prev.next_entry = entry.next_entry;
+            } else {
+                (*descriptor).first_entry = entry.next_entry;
+            }
+            if let Some(next) = entry.next_entry.as_mut() {
+                next.prev_entry = entry.prev_entry;
+            }
+            (*descriptor).relevant_entry = self.entry;
+            (*descriptor).action_flag = JIT_UNREGISTER_FN;
+            __jit_debug_register_code();
+
+            (*descriptor).relevant_entry = std::ptr::null_mut();
+            drop(Box::from_raw(self.entry));
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::io;
+
+use super::cell::Cell;
+use super::error::FunjitError;
+use super::jit::{Budget, ExitStatus, Jit, Watches, IO};
+use super::space;
+
+/// How far into a `Tape` a program has got.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TapeCursor {
+    pub inputs: usize,
+    pub directions: usize,
+    pub outputs: u64,
+}
+
+/// Everything a program has read and every direction `?` has taken, so that running it again from
+/// an earlier point does exactly what it did the first time. Output that's already been written
+/// isn't written again.
+#[derive(Clone, Debug, Default)]
+pub struct Tape {
+    // characters and numbers alike, with `None` at the end of the input
+    inputs: Vec<Option<isize>>,
+    // indexes into `random::DIRECTIONS`
+    directions: Vec<u8>,
+    outputs: u64,
+    pub cursor: TapeCursor,
+}
+
+impl Tape {
+    /// The next input, either replayed or read with `read` and recorded.
+    pub fn input(
+        &mut self,
+        read: impl FnOnce() -> io::Result<Option<isize>>,
+    ) -> io::Result<Option<isize>> {
+        let input = match self.inputs.get(self.cursor.inputs) {
+            Some(&input) => input,
+            None => {
+                let input = read()?;
+                self.inputs.push(input);
+                input
+            }
+        };
+        self.cursor.inputs += 1;
+        Ok(input)
+    }
+
+    /// The next direction for `?`, either replayed or chosen with `choose` and recorded.
+    pub fn direction(&mut self, choose: impl FnOnce() -> usize) -> usize {
+        let index = match self.directions.get(self.cursor.directions) {
+            Some(&index) => index as usize,
+            None => {
+                let index = choose();
+                self.directions.push(index as u8);
+                index
+            }
+        };
+        self.cursor.directions += 1;
+        index
+    }
+
+    /// Count an output, returning whether it's new rather than one that was already written.
+    pub fn output(&mut self) -> bool {
+        self.cursor.outputs += 1;
+        let new = self.cursor.outputs > self.outputs;
+        self.outputs = self.outputs.max(self.cursor.outputs);
+        new
+    }
+}
+
+/// The state of a program after some number of steps, which it can be returned to.
+#[derive(Clone, Debug)]
+pub struct Snapshot<C: Cell> {
+    pub steps: u64,
+    pub cells: space::Funge93<C>,
+    pub stack: Vec<C>,
+    pub pc: space::Pos,
+    pub delta: space::Pos,
+    pub(crate) string_mode: bool,
+    pub tape: TapeCursor,
+}
+
+/// Runs a program while recording it, keeping snapshots along the way so that it can be taken
+/// back to any earlier step.
+pub struct History<C: Cell> {
+    // in the order they were taken, which is also the order of their steps
+    snapshots: Vec<Snapshot<C>>,
+    interval: u64,
+}
+
+impl<C: Cell> History<C> {
+    /// Snapshots are taken this many steps apart to begin with.
+    pub const INTERVAL: u64 = 1000;
+    /// Once there are this many snapshots, every other one is dropped and the interval doubles.
+    pub const MAX_SNAPSHOTS: usize = 256;
+
+    /// Start recording `jit`, taking a snapshot of where it is now.
+    pub fn new<I: IO>(jit: &mut Jit<I, C>) -> Self {
+        jit.start_recording();
+        History {
+            snapshots: vec![jit.snapshot()],
+            interval: Self::INTERVAL,
+        }
+    }
+
+    // The step at which the next snapshot is due.
+    fn next_snapshot(&self) -> u64 {
+        self.snapshots.last().map_or(0, |last| last.steps + self.interval)
+    }
+
+    fn record<I: IO>(&mut self, jit: &Jit<I, C>) {
+        if jit.steps < self.next_snapshot() {
+            return;
+        }
+
+        if self.snapshots.len() >= Self::MAX_SNAPSHOTS {
+            let mut index = 0;
+            self.snapshots.retain(|_| {
+                index += 1;
+                index % 2 == 1
+            });
+            self.interval *= 2;
+        }
+        self.snapshots.push(jit.snapshot());
+    }
+
+    /// Run `jit` until it stops or uses up `budget`, as `Jit::run_for` or `Jit::run` do, taking
+    /// snapshots as it goes.
+    pub fn run<I: IO>(
+        &mut self,
+        jit: &mut Jit<I, C>,
+        budget: Option<Budget>,
+    ) -> Result<ExitStatus, FunjitError> {
+        let start = jit.steps;
+        let goal = match budget {
+            Some(Budget::Steps(steps)) => Some(start.saturating_add(steps)),
+            Some(Budget::Traces(_)) => {
+                let status = jit.run_for(budget.unwrap());
+                self.record(jit);
+                return status;
+            }
+            None => None,
+        };
+
+        // run in slices that end where snapshots are due
+        loop {
+            let until = goal.map_or(self.next_snapshot(), |goal| goal.min(self.next_snapshot()));
+            let status = jit.run_for(Budget::Steps(until.saturating_sub(jit.steps).max(1)))?;
+            self.record(jit);
+
+            if status != ExitStatus::Paused || goal.is_some_and(|goal| jit.steps >= goal) {
+                return Ok(status);
+            }
+            // a slice that stops on a breakpoint doesn't report it, as it could be resuming
+            if jit.steps != start && jit.watches().breakpoints.contains(&jit.pc) {
+                return Ok(ExitStatus::Breakpoint);
+            }
+        }
+    }
+
+    /// Take `jit` to the point where it's taken `steps` steps, going back to an earlier snapshot if
+    /// needed and replaying from there, without stopping at breakpoints or watchpoints on the way.
+    pub fn seek<I: IO>(
+        &mut self,
+        jit: &mut Jit<I, C>,
+        steps: u64,
+    ) -> Result<ExitStatus, FunjitError> {
+        if steps < jit.steps {
+            let snapshot = self
+                .snapshots
+                .iter()
+                .rev()
+                .find(|snapshot| snapshot.steps <= steps)
+                .expect("No snapshot from the start of the program");
+            jit.restore(snapshot);
+        }
+
+        let watches = jit.watches().clone();
+        jit.set_watches(Watches::default());
+        let result = match steps - jit.steps {
+            0 => Ok(ExitStatus::Paused),
+            remaining => self.run(jit, Some(Budget::Steps(remaining))),
+        };
+        jit.set_watches(watches);
+        result
+    }
+}
@@ -2,10 +2,24 @@
 use dynasmrt::mmap::ExecutableBuffer;
 
 use dynasmrt::{dynasm, DynasmApi, DynasmLabelApi};
-use std::collections::{HashMap, HashSet};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::fmt;
 use std::io::{self, prelude::*};
-
+use std::sync::atomic::AtomicBool;
+use std::time::{Duration, Instant};
+
+use super::cancel::CancelToken;
+use super::cell::{Arith, Cell, Overflow, Width};
+use super::dump::BlockDumper;
+use super::error::{FunjitError, Limit};
+use super::gdb::{DebugInfo, Registration};
+use super::history::{Snapshot, Tape};
+use super::perf::Perf;
+use super::profile::{BlockCounter, Profile};
+use super::random::{self, Directions};
+use super::reader;
 use super::space;
+use super::trace::{TraceLevel, Tracer};
 
 macro_rules! funjit_dynasm {
     ($ops:ident $($t:tt)*) => {
@@ -31,49 +45,88 @@ macro_rules! prologue {
 }
 
 macro_rules! set_pc {
-    ($ops:ident, $i:ident, $pc:expr) => {
+    ($ops:ident, $i:ident, $c:ident, $pc:expr) => {
         funjit_dynasm!($ops ; mov rsi, QWORD $pc.x as _);
         funjit_dynasm!($ops ; mov rdx, QWORD $pc.y as _);
-        call_external!($ops, Jit::<$i>::set_pc);
+        call_external!($ops, Jit::<$i, $c>::set_pc);
     }
 }
 
 macro_rules! set_delta {
-    ($ops:ident, $i:ident, $pc:expr) => {
+    ($ops:ident, $i:ident, $c:ident, $pc:expr) => {
         funjit_dynasm!($ops ; mov rsi, QWORD $pc.x as _);
         funjit_dynasm!($ops ; mov rdx, QWORD $pc.y as _);
-        call_external!($ops, Jit::<$i>::set_delta);
+        call_external!($ops, Jit::<$i, $c>::set_delta);
     }
 }
 
 macro_rules! epilogue {
-    ($ops:ident, $terminates:expr) => {
+    ($ops:ident, $exit:expr) => {
         funjit_dynasm!($ops
-            ; mov rax, QWORD $terminates as _
+            ; mov rax, QWORD $exit as _
             ; leave
             ; ret
         )
     }
 }
 
+// Leave the block if the call that was just made returned false, uncounting the `$remaining`
+// steps of the block that won't be executed.
+macro_rules! check_leave {
+    ($ops:ident, $remaining:expr) => {
+        funjit_dynasm!($ops
+            ; test al, al
+            ; mov rcx, QWORD $remaining as _
+            ; jz ->leave
+        )
+    }
+}
+
 macro_rules! call_external {
     ($ops:ident, $addr:expr) => {
         funjit_dynasm!($ops
             ; mov rdi, [rsp]
-            ; mov rax, QWORD $addr as _
+            ; mov rax, QWORD $addr as *const () as _
             ; call rax
         )
     }
 }
 
+// Push rsi onto the stack, leaving the block if that would exceed the stack limit.
+macro_rules! push {
+    ($ops:ident, $i:ident, $c:ident, $remaining:expr) => {
+        call_external!($ops, Jit::<$i, $c>::push);
+        check_leave!($ops, $remaining);
+    }
+}
+
+// Call a method that needs to know the position of the instruction being executed.
+macro_rules! call_at {
+    ($ops:ident, $addr:expr, $pos:expr) => {
+        funjit_dynasm!($ops ; mov rsi, QWORD $pos.x as _);
+        funjit_dynasm!($ops ; mov rdx, QWORD $pos.y as _);
+        call_external!($ops, $addr);
+    }
+}
+
+// Call a method that also needs to know the direction the IP was moving in when it reached the
+// instruction.
+macro_rules! call_at_origin {
+    ($ops:ident, $addr:expr, $origin:expr) => {
+        funjit_dynasm!($ops ; mov rcx, QWORD $origin.delta.x as _);
+        funjit_dynasm!($ops ; mov r8, QWORD $origin.delta.y as _);
+        call_at!($ops, $addr, $origin.pos);
+    }
+}
+
 // a b --
-// rax = a
-// rsi = b
+// rsi = a
+// rax = b
 macro_rules! binop {
-    ($ops:ident, $i:ident) => {
-        call_external!($ops, Jit::<$i>::pop);
+    ($ops:ident, $i:ident, $c:ident) => {
+        call_external!($ops, Jit::<$i, $c>::pop);
         funjit_dynasm!($ops ; mov [rsp + 8], rax);
-        call_external!($ops, Jit::<$i>::pop);
+        call_external!($ops, Jit::<$i, $c>::pop);
         funjit_dynasm!($ops
             ; mov rsi, rax
             ; mov rax, [rsp + 8]
@@ -81,282 +134,1436 @@ macro_rules! binop {
     }
 }
 
+// Hand the operation on rsi and rax that compiled code couldn't complete inline to
+// `Jit::arith_slow`, which pushes the result.
+macro_rules! arith_slow {
+    ($ops:ident, $i:ident, $c:ident, $op:expr, $pos:expr, $remaining:expr) => {
+        funjit_dynasm!($ops
+            ; mov r8, rsi
+            ; mov r9, rax
+            ; mov rcx, QWORD $op as _
+        );
+        call_at!($ops, Jit::<$i, $c>::arith_slow, $pos);
+        check_leave!($ops, $remaining);
+    }
+}
+
+// Sign-extend the low `$bits` of rsi, so that the result of an arithmetic operation wraps at the
+// width of the cell type.
+macro_rules! narrow {
+    ($ops:ident, $bits:expr) => {
+        if $bits == 32 {
+            funjit_dynasm!($ops ; movsxd rsi, esi);
+        }
+    }
+}
+
+/// Where an instruction in a block was read from, and the direction the IP was moving in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Origin {
+    pub pos: space::Pos,
+    pub delta: space::Pos,
+    /// How many steps into the block the instruction is executed.
+    pub step: u64,
+}
+
 #[derive(Default)]
 pub struct Block {
     pub code: String,
+    /// The origin of each instruction in `code`.
+    pub origins: Vec<Origin>,
     pub loops: bool,
     pub mutates: bool,
     pub terminates: bool,
+    /// The block ends by executing the `?` at `pc`.
+    pub random: bool,
     pub pc: space::Pos,
     pub delta: space::Pos,
+    /// The number of cells the IP lands on when executing the block, which includes the spaces,
+    /// arrows and trampolines that don't appear in `code`.
+    pub steps: u64,
+    /// The cells the IP lands on, in order.
+    pub cells: Vec<Origin>,
 }
 
+/// The compiled blocks that follow a `?`, as code addresses indexed like `random::DIRECTIONS`.
+/// Blocks that end at the `?` jump straight to them, and return `BlockExit::Unlinked` for `Jit::run`
+/// to fill in any that are missing.
+pub type Successors = [usize; 4];
+
 impl Block {
-    pub fn compile<I: IO>(&self) -> CompiledBlock<I> {
+    /// Where the block starts, and the direction it's entered in.
+    pub fn entry(&self) -> (space::Pos, space::Pos) {
+        self.cells
+            .first()
+            .map_or((self.pc, self.delta), |cell| (cell.pos, cell.delta))
+    }
+
+    /// Compile the block, linking it to `successors` if it ends at a `?`. When `inline_rng` is
+    /// set, directions come from `Jit::rng`, rather than a call to `Jit::random_direction`. The
+    /// block stops at its next entry once `cancelled` is set, and reports each pass or step to
+    /// `Jit::tracer` at the given `trace` level. When `options.profile` is set, it counts its
+    /// passes for `Jit::profile`.
+    pub fn compile<I: IO, C: Cell>(
+        &self,
+        options: &Options,
+        successors: Option<&Successors>,
+        inline_rng: bool,
+        cancelled: &AtomicBool,
+        trace: Option<TraceLevel>,
+    ) -> CompiledBlock<I, C> {
         let mut ops = dynasmrt::x64::Assembler::new().unwrap();
 
         let mut string_mode = false;
 
         let fun = prologue!(ops);
-        for c in self.code.chars() {
+        // where the code for each instruction starts, for debuggers, with the prologue belonging
+        // to the first cell
+        let mut lines = vec![(fun.0, self.entry().0)];
+        Self::compile_charge::<I, C>(&mut ops, self.steps, cancelled);
+
+        // passes are counted once they've been charged for, and uncounted when they leave early
+        let counter = options.profile.then(|| BlockCounter::new(&self.cells));
+        if let Some(counter) = &counter {
+            funjit_dynasm!(ops
+                ; mov rax, QWORD counter.addr() as _
+                ; add QWORD [rax], 1
+            );
+        }
+
+        if let (Some(TraceLevel::Block), Some(start)) = (trace, self.cells.first()) {
+            funjit_dynasm!(ops ; mov r9, QWORD self.steps as _);
+            call_at_origin!(ops, Jit::<I, C>::trace_block, start);
+            check_leave!(ops, self.steps);
+        }
+        // the steps that have been traced so far
+        let mut traced = 0;
+
+        for (c, origin) in self.code.chars().zip(self.origins.iter()) {
+            let pos = origin.pos;
+            let remaining = self.steps - origin.step - 1;
+            lines.push((ops.offset().0, pos));
+
+            if trace == Some(TraceLevel::Instruction) {
+                let cells = &self.cells[traced..=origin.step as usize];
+                Self::compile_trace::<I, C>(&mut ops, cells, self.steps);
+                traced = origin.step as usize + 1;
+            }
+
             match c {
                 '"' => string_mode = !string_mode,
 
                 c if string_mode => {
                     funjit_dynasm!(ops ; mov rsi, QWORD c as _);
-                    call_external!(ops, Jit::<I>::push);
+                    push!(ops, I, C, remaining);
                 }
 
                 c @ '0'..='9' => {
                     let val = c as isize - '0' as isize;
                     funjit_dynasm!(ops ; mov rsi, QWORD val as _);
-                    call_external!(ops, Jit::<I>::push);
+                    push!(ops, I, C, remaining);
                 }
 
                 // would be nice to enforce that this is also the end of the instruction stream
                 '@' => break,
 
-                ',' => call_external!(ops, Jit::<I>::output),
-                '.' => call_external!(ops, Jit::<I>::output_number),
-                '~' => call_external!(ops, Jit::<I>::input),
-                '&' => call_external!(ops, Jit::<I>::input_number),
-
-                ':' => {
-                    call_external!(ops, Jit::<I>::peek);
-                    funjit_dynasm!(ops ; mov rsi, rax);
-                    call_external!(ops, Jit::<I>::push);
+                ',' => {
+                    call_at!(ops, Jit::<I, C>::output, pos);
+                    check_leave!(ops, remaining);
                 }
-
-                '\\' => {
-                    call_external!(ops, Jit::<I>::pop);
-                    funjit_dynasm!(ops ; mov [rsp + 8], rax);
-                    call_external!(ops, Jit::<I>::pop);
-                    funjit_dynasm!(ops
-                        ; mov rsi, [rsp + 8]
-                        ; mov [rsp + 8], rax
-                    );
-                    call_external!(ops, Jit::<I>::push);
-                    funjit_dynasm!(ops ; mov rsi, [rsp + 8]);
-                    call_external!(ops, Jit::<I>::push);
+                '.' => {
+                    call_at!(ops, Jit::<I, C>::output_number, pos);
+                    check_leave!(ops, remaining);
                 }
-
-                '!' => {
-                    call_external!(ops, Jit::<I>::pop);
-                    funjit_dynasm!(ops
-                        ; xor rsi, rsi
-                        ; cmp rax, rsi
-                        ; jne >write
-                        ; inc rsi
-                        ; write:
-                    );
-                    call_external!(ops, Jit::<I>::push);
+                '~' => {
+                    call_at_origin!(ops, Jit::<I, C>::input, origin);
+                    check_leave!(ops, remaining);
                 }
-
-                '`' => {
-                    binop!(ops, I);
-                    funjit_dynasm!(ops
-                        ; cmp rax, rsi
-                        ; xor rsi, rsi
-                        ; jle >write
-                        ; inc rsi
-                        ; write:
-                    );
-                    call_external!(ops, Jit::<I>::push);
+                '&' => {
+                    call_at_origin!(ops, Jit::<I, C>::input_number, origin);
+                    check_leave!(ops, remaining);
                 }
-
                 'g' => {
-                    call_external!(ops, Jit::<I>::get);
-                    funjit_dynasm!(ops ; mov rsi, rax);
-                    call_external!(ops, Jit::<I>::push);
+                    call_external!(ops, Jit::<I, C>::get);
+                    check_leave!(ops, remaining);
                 }
+                '$' => call_external!(ops, Jit::<I, C>::pop),
+
+                // stack operations depend on the cell width. Anything else is a no-op, like it is
+                // to the interpreter, and its step has already been charged
+                c => match C::WIDTH {
+                    Width::Native(bits) => Self::compile_native::<I, C>(
+                        &mut ops,
+                        c,
+                        pos,
+                        remaining,
+                        bits,
+                        options.overflow,
+                    ),
+                    Width::Boxed => Self::compile_boxed::<I, C>(&mut ops, c, pos, remaining),
+                },
+            }
+        }
 
-                '+' => {
-                    binop!(ops, I);
-                    funjit_dynasm!(ops ; add rsi, rax);
-                    call_external!(ops, Jit::<I>::push);
-                }
+        // the code that leaves the block belongs to the last cell
+        if let Some(last) = self.cells.last() {
+            lines.push((ops.offset().0, last.pos));
+        }
 
-                '-' => {
-                    binop!(ops, I);
-                    funjit_dynasm!(ops ; sub rsi, rax);
-                    call_external!(ops, Jit::<I>::push);
+        if trace == Some(TraceLevel::Instruction) {
+            Self::compile_trace::<I, C>(&mut ops, &self.cells[traced..], self.steps);
+        }
+
+        if !self.random {
+            set_pc!(ops, I, C, self.pc);
+            set_delta!(ops, I, C, self.delta);
+        }
+
+        if self.random {
+            Self::compile_random::<I, C>(&mut ops, self.pc, successors.unwrap(), inline_rng);
+        } else if self.loops {
+            funjit_dynasm!(ops
+                ; lea rax, [->entry]
+                ; jmp rax
+            );
+        } else if self.terminates {
+            epilogue!(ops, BlockExit::Terminate);
+        } else {
+            epilogue!(ops, BlockExit::Continue);
+        }
+
+        let steps = std::mem::offset_of!(Jit<I, C>, steps) as i32;
+        funjit_dynasm!(ops
+            ; ->leave:
+            ; mov rdi, [rsp]
+            ; sub [rdi + steps], rcx
+        );
+        if let Some(counter) = &counter {
+            // rcx is the number of cells that weren't executed
+            funjit_dynasm!(ops
+                ; mov rax, QWORD counter.addr() as _
+                ; mov rdx, QWORD (self.steps + 1) as _
+                ; sub rdx, rcx
+                ; add QWORD [rax + rdx * 8], 1
+            );
+        }
+        let (entry, delta) = self.entry();
+        funjit_dynasm!(ops
+            ; mov rsi, QWORD self.steps as _
+            ; sub rsi, rcx
+            ; mov rdx, QWORD entry.x as _
+            ; mov rcx, QWORD entry.y as _
+            ; mov r8, QWORD delta.x as _
+            ; mov r9, QWORD delta.y as _
+        );
+        call_external!(ops, Jit::<I, C>::leave_block);
+        funjit_dynasm!(ops
+            ; ->stop:
+        );
+        epilogue!(ops, BlockExit::Leave);
+
+        let buffer = ops.finalize().unwrap();
+        let code = unsafe {
+            std::mem::transmute::<*const u8, extern "sysv64" fn(&mut Jit<I, C>) -> u64>(
+                buffer.ptr(fun),
+            )
+        };
+
+        CompiledBlock {
+            registration: None,
+            buffer,
+            code,
+            steps: self.steps,
+            cells: self.cells.iter().map(|cell| cell.pos).collect(),
+            origins: self.cells.clone(),
+            lines,
+            counter,
+        }
+    }
+
+    // Count the steps the block is about to execute, checking the budget when `Jit::check_at` is
+    // passed or the program has been cancelled. This runs on every entry to the block, including
+    // the back-edges of loops.
+    fn compile_charge<I: IO, C: Cell>(
+        ops: &mut dynasmrt::x64::Assembler,
+        steps: u64,
+        cancelled: &AtomicBool,
+    ) {
+        let count = std::mem::offset_of!(Jit<I, C>, steps) as i32;
+        let check_at = std::mem::offset_of!(Jit<I, C>, check_at) as i32;
+
+        funjit_dynasm!(ops
+            ; mov rdi, [rsp]
+            ; mov rsi, QWORD steps as _
+            ; mov rax, [rdi + count]
+            ; add rax, rsi
+            ; mov [rdi + count], rax
+            ; cmp rax, [rdi + check_at]
+            ; ja >check
+            ; mov rax, QWORD cancelled.as_ptr() as _
+            ; cmp BYTE [rax], 0
+            ; je >charged
+            ; check:
+        );
+        call_external!(ops, Jit::<I, C>::check_budget);
+        funjit_dynasm!(ops
+            ; test al, al
+            ; jz ->stop
+            ; charged:
+        );
+    }
+
+    // Report each of `cells` to `Jit::trace_step` before it's executed, leaving the block if that
+    // fails.
+    fn compile_trace<I: IO, C: Cell>(
+        ops: &mut dynasmrt::x64::Assembler,
+        cells: &[Origin],
+        steps: u64,
+    ) {
+        for cell in cells {
+            // the step being traced is uncounted along with the rest if it can't be
+            let remaining = steps - cell.step - 1;
+            let unexecuted = remaining + 1;
+            funjit_dynasm!(ops ; mov r9, QWORD remaining as _);
+            call_at_origin!(ops, Jit::<I, C>::trace_step, cell);
+            check_leave!(ops, unexecuted);
+        }
+    }
+
+    // Pick a direction for the `?` at `pos`, and jump to the block that follows in that direction.
+    fn compile_random<I: IO, C: Cell>(
+        ops: &mut dynasmrt::x64::Assembler,
+        pos: space::Pos,
+        successors: &Successors,
+        inline_rng: bool,
+    ) {
+        let turns = (std::mem::offset_of!(Jit<I, C>, stats)
+            + std::mem::offset_of!(Stats, random_turns)) as i32;
+        funjit_dynasm!(ops
+            ; mov rdi, [rsp]
+            ; inc QWORD [rdi + turns]
+        );
+
+        if inline_rng {
+            let state = (std::mem::offset_of!(Jit<I, C>, rng) + random::XorShift::STATE_OFFSET) as i32;
+
+            // random::XorShift::next, keeping the top two bits as the direction
+            funjit_dynasm!(ops
+                ; mov rax, [rdi + state]
+                ; mov rcx, rax
+                ; shl rcx, 13
+                ; xor rax, rcx
+                ; mov rcx, rax
+                ; shr rcx, 7
+                ; xor rax, rcx
+                ; mov rcx, rax
+                ; shl rcx, 17
+                ; xor rax, rcx
+                ; mov [rdi + state], rax
+                ; shr rax, 62
+            );
+        } else {
+            call_external!(ops, Jit::<I, C>::random_direction);
+        }
+
+        funjit_dynasm!(ops
+            ; mov [rsp + 8], rax
+            ; mov rcx, rax
+        );
+        call_at!(ops, Jit::<I, C>::turn, pos);
+
+        // the successor replaces this block's frame, so that it returns straight to `Jit::run`
+        funjit_dynasm!(ops
+            ; mov rax, [rsp + 8]
+            ; mov rcx, QWORD successors.as_ptr() as _
+            ; mov rax, [rcx + rax * 8]
+            ; test rax, rax
+            ; jz >unlinked
+            ; mov rdi, [rsp]
+            ; leave
+            ; jmp rax
+            ; unlinked:
+        );
+        epilogue!(ops, BlockExit::Unlinked);
+    }
+
+    // Stack operations for cells that fit in a register are performed inline. Results that
+    // overflow the cell width are narrowed, or handed to `Jit::arith_slow` when overflow needs to
+    // saturate or trap.
+    fn compile_native<I: IO, C: Cell>(
+        ops: &mut dynasmrt::x64::Assembler,
+        c: char,
+        pos: space::Pos,
+        remaining: u64,
+        bits: u32,
+        overflow: Overflow,
+    ) {
+        match c {
+            ':' => {
+                call_external!(ops, Jit::<I, C>::peek);
+                funjit_dynasm!(ops ; mov rsi, rax);
+                push!(ops, I, C, remaining);
+            }
+
+            '\\' => {
+                call_external!(ops, Jit::<I, C>::pop);
+                funjit_dynasm!(ops ; mov [rsp + 8], rax);
+                call_external!(ops, Jit::<I, C>::pop);
+                funjit_dynasm!(ops
+                    ; mov rsi, [rsp + 8]
+                    ; mov [rsp + 8], rax
+                );
+                push!(ops, I, C, remaining);
+                funjit_dynasm!(ops ; mov rsi, [rsp + 8]);
+                push!(ops, I, C, remaining);
+            }
+
+            '!' => {
+                call_external!(ops, Jit::<I, C>::pop);
+                funjit_dynasm!(ops
+                    ; xor rsi, rsi
+                    ; cmp rax, rsi
+                    ; jne >write
+                    ; inc rsi
+                    ; write:
+                );
+                push!(ops, I, C, remaining);
+            }
+
+            '`' => {
+                binop!(ops, I, C);
+                funjit_dynasm!(ops
+                    ; xor ecx, ecx
+                    ; cmp rax, rsi
+                    ; setl cl
+                    ; mov rsi, rcx
+                );
+                push!(ops, I, C, remaining);
+            }
+
+            '+' | '-' | '*' if overflow == Overflow::Wrap => {
+                binop!(ops, I, C);
+                match c {
+                    '+' => funjit_dynasm!(ops ; add rsi, rax),
+                    '-' => funjit_dynasm!(ops ; sub rsi, rax),
+                    _ => funjit_dynasm!(ops ; imul rsi, rax),
                 }
+                narrow!(ops, bits);
+                push!(ops, I, C, remaining);
+            }
 
-                '*' => {
-                    binop!(ops, I);
-                    funjit_dynasm!(ops ; imul rsi, rax);
-                    call_external!(ops, Jit::<I>::push);
+            '+' | '-' | '*' => {
+                binop!(ops, I, C);
+                funjit_dynasm!(ops ; mov rcx, rsi);
+                let op = match c {
+                    '+' => {
+                        funjit_dynasm!(ops ; add rcx, rax);
+                        Arith::Add
+                    }
+                    '-' => {
+                        funjit_dynasm!(ops ; sub rcx, rax);
+                        Arith::Sub
+                    }
+                    _ => {
+                        funjit_dynasm!(ops ; imul rcx, rax);
+                        Arith::Mul
+                    }
+                };
+
+                // narrower operands can't overflow 64 bits, so check that the result survives
+                // being narrowed instead
+                match bits {
+                    32 => funjit_dynasm!(ops ; movsxd rdx, ecx ; cmp rdx, rcx ; jne >overflow),
+                    _ => funjit_dynasm!(ops ; jo >overflow),
                 }
 
-                '/' => {
-                    call_external!(ops, Jit::<I>::pop);
-                    funjit_dynasm!(ops; mov [rsp + 0x8], rax);
-                    call_external!(ops, Jit::<I>::pop);
+                funjit_dynasm!(ops ; mov rsi, rcx);
+                push!(ops, I, C, remaining);
+                funjit_dynasm!(ops
+                    ; jmp >done
+                    ; overflow:
+                );
+                arith_slow!(ops, I, C, op, pos, remaining);
+                funjit_dynasm!(ops ; done:);
+            }
+
+            '/' | '%' => {
+                call_external!(ops, Jit::<I, C>::pop);
+                funjit_dynasm!(ops; mov [rsp + 0x8], rax);
+                call_external!(ops, Jit::<I, C>::pop);
+                funjit_dynasm!(ops
+                    ; mov rcx, [rsp + 0x8]
+                    ; test rcx, rcx
+                    ; jz >zero
+                    // idiv traps on MIN / -1, but dividing by -1 is just negation
+                    ; cmp rcx, -1
+                    ; je >negate
+                    ; cqo
+                    ; idiv rcx
+                );
+
+                if c == '/' && overflow == Overflow::Wrap {
                     funjit_dynasm!(ops
-                        ; xor rdx, rdx
-                        ; idiv [rsp + 0x8]
                         ; mov rsi, rax
+                        ; jmp >push
+                        ; negate:
+                        ; neg rax
+                        ; mov rsi, rax
+                        ; jmp >push
                     );
-                    call_external!(ops, Jit::<I>::push);
-                }
-
-                '%' => {
-                    call_external!(ops, Jit::<I>::pop);
-                    funjit_dynasm!(ops; mov [rsp + 0x8], rax);
-                    call_external!(ops, Jit::<I>::pop);
+                } else if c == '/' {
+                    // negating MIN overflows
+                    funjit_dynasm!(ops
+                        ; mov rsi, rax
+                        ; jmp >push
+                        ; negate:
+                        ; mov rsi, rax
+                        ; mov rax, rcx
+                    );
+                    arith_slow!(ops, I, C, Arith::Div, pos, remaining);
+                    funjit_dynasm!(ops ; jmp >done);
+                } else {
                     funjit_dynasm!(ops
-                        ; xor rdx, rdx
-                        ; idiv [rsp + 0x8]
                         ; mov rsi, rdx
+                        ; jmp >push
+                        ; negate:
+                        ; xor rsi, rsi
+                        ; jmp >push
                     );
-                    call_external!(ops, Jit::<I>::push);
                 }
 
-                '$' => call_external!(ops, Jit::<I>::pop),
+                let op = if c == '/' { Arith::Div } else { Arith::Rem };
+                funjit_dynasm!(ops
+                    ; zero:
+                    ; mov rcx, QWORD op as _
+                    ; mov r8, rax
+                );
+                call_at!(ops, Jit::<I, C>::divide_by_zero, pos);
+                check_leave!(ops, remaining);
+                funjit_dynasm!(ops
+                    ; jmp >done
+                    ; push:
+                );
+                narrow!(ops, bits);
+                push!(ops, I, C, remaining);
+                funjit_dynasm!(ops ; done:);
+            }
 
-                _ => {
-                    println!("Unhandled instruction: {}\n", c);
-                    break;
+            _ => (),
+        }
+    }
+
+    // Boxed cells can't be moved through registers, so every operation that inspects them is a
+    // call back into the `Jit`.
+    fn compile_boxed<I: IO, C: Cell>(
+        ops: &mut dynasmrt::x64::Assembler,
+        c: char,
+        pos: space::Pos,
+        remaining: u64,
+    ) {
+        match c {
+            ':' | '\\' | '!' | '`' => {
+                match c {
+                    ':' => call_external!(ops, Jit::<I, C>::dup),
+                    '\\' => call_external!(ops, Jit::<I, C>::swap),
+                    '!' => call_external!(ops, Jit::<I, C>::not),
+                    _ => call_external!(ops, Jit::<I, C>::greater),
                 }
+                check_leave!(ops, remaining);
             }
+            '+' | '-' | '*' | '/' | '%' => {
+                let op = match c {
+                    '+' => Arith::Add,
+                    '-' => Arith::Sub,
+                    '*' => Arith::Mul,
+                    '/' => Arith::Div,
+                    _ => Arith::Rem,
+                };
+                funjit_dynasm!(ops ; mov rcx, QWORD op as _);
+                call_at!(ops, Jit::<I, C>::arith, pos);
+                check_leave!(ops, remaining);
+            }
+            _ => (),
         }
+    }
+}
 
-        set_pc!(ops, I, self.pc);
-        set_delta!(ops, I, self.delta);
+/// How a compiled block hands control back to `Jit::run`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockExit {
+    Continue,
+    Terminate,
+    /// A call made by the block asked to leave early, recording an error in `Jit::error`, a
+    /// reason to stop in `Jit::stopped`, or moving the pc.
+    Leave,
+    /// The block executed a `?`, and the block that follows in the chosen direction hasn't been
+    /// linked to it yet.
+    Unlinked,
+}
 
-        if self.loops {
-            funjit_dynasm!(ops
-                ; lea rax, [->entry]
-                ; jmp rax
-            );
-        } else {
-            epilogue!(ops, self.terminates);
+impl BlockExit {
+    fn from_code(code: u64) -> Self {
+        match code {
+            0 => BlockExit::Continue,
+            1 => BlockExit::Terminate,
+            2 => BlockExit::Leave,
+            _ => BlockExit::Unlinked,
         }
+    }
+}
 
-        let buffer = ops.finalize().unwrap();
-        let code = unsafe { std::mem::transmute(buffer.ptr(fun)) };
+pub struct CompiledBlock<I: IO, C: Cell> {
+    // GDB is told the block is gone before its code is freed
+    registration: Option<Registration>,
+    buffer: dynasmrt::mmap::ExecutableBuffer,
+    code: extern "sysv64" fn(&mut Jit<I, C>) -> u64,
+    steps: u64,
+    cells: HashSet<space::Pos>,
+    // the cell the IP lands on at each step, to move the pc to when the block fails
+    origins: Vec<Origin>,
+    lines: Vec<(usize, space::Pos)>,
+    counter: Option<BlockCounter>,
+}
 
-        CompiledBlock {
-            _buffer: buffer,
-            code,
+impl<I: IO, C: Cell> CompiledBlock<I, C> {
+    pub fn run(&self, state: &mut Jit<I, C>) -> BlockExit {
+        BlockExit::from_code((self.code)(state))
+    }
+
+    /// The number of bytes of machine code in the block.
+    pub fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The number of steps one pass through the block takes.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Whether the IP lands on any of `breakpoints` in the block.
+    pub fn touches(&self, breakpoints: &HashSet<space::Pos>) -> bool {
+        !breakpoints.is_empty() && !self.cells.is_disjoint(breakpoints)
+    }
+
+    /// The address that other blocks jump to.
+    pub fn addr(&self) -> usize {
+        self.code as usize
+    }
+
+    /// The block's machine code, which starts at `addr`.
+    pub fn machine_code(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Where the code compiled from each cell starts, as an offset into `machine_code`, in order.
+    pub fn lines(&self) -> &[(usize, space::Pos)] {
+        &self.lines
+    }
+}
+
+// The blocks compiled so far, which are kept between calls to `Jit::run` so that a paused
+// program doesn't have to be compiled again.
+struct Cache<I: IO, C: Cell> {
+    // blocks are keyed by delta as well as pc, as reflection can lead to a cell being entered
+    // from a new direction
+    blocks: HashMap<(space::Pos, space::Pos), CompiledBlock<I, C>>,
+    successors: HashMap<space::Pos, Box<Successors>>,
+    code_size: usize,
+    // the `?` and direction index of a block that's waiting to be linked to the block at pc
+    pending_link: Option<(space::Pos, usize)>,
+    // blocks that haven't been compiled yet, with the number of times they've been interpreted.
+    // They're kept as they were traced, to be compiled once they're hot enough
+    cold: HashMap<(space::Pos, space::Pos), (u32, Block)>,
+}
+
+impl<I: IO, C: Cell> Cache<I, C> {
+    fn new() -> Self {
+        Cache {
+            blocks: HashMap::new(),
+            successors: HashMap::new(),
+            code_size: 0,
+            pending_link: None,
+            cold: HashMap::new(),
         }
     }
+
+    fn clear(&mut self) {
+        self.blocks.clear();
+        self.successors.clear();
+        self.code_size = 0;
+        self.cold.clear();
+    }
 }
 
-pub struct CompiledBlock<I: IO> {
-    _buffer: dynasmrt::mmap::ExecutableBuffer,
-    code: extern "sysv64" fn(&mut Jit<I>) -> bool,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Befunge93,
+    Befunge98,
 }
 
-impl<I: IO> CompiledBlock<I> {
-    pub fn run(&self, state: &mut Jit<I>) -> bool {
-        (self.code)(state)
+impl Dialect {
+    /// The standard behavior of `/` and `%` when the divisor is zero.
+    pub fn division_by_zero(&self) -> DivisionByZero {
+        match self {
+            Dialect::Befunge93 => DivisionByZero::Ask,
+            Dialect::Befunge98 => DivisionByZero::Zero,
+        }
     }
 }
 
+impl std::str::FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "93" => Ok(Dialect::Befunge93),
+            "98" => Ok(Dialect::Befunge98),
+            _ => Err(format!("Unknown dialect: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DivisionByZero {
+    /// Prompt for the result with `IO::prompt` and read it from the input, as the Befunge-93
+    /// reference implementation does. The result is -1 at the end of the input.
+    Ask,
+    /// Push zero, as Befunge-98 requires.
+    Zero,
+    /// Stop the program with an error.
+    Trap,
+}
+
+impl std::str::FromStr for DivisionByZero {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(DivisionByZero::Ask),
+            "zero" => Ok(DivisionByZero::Zero),
+            "trap" => Ok(DivisionByZero::Trap),
+            _ => Err(format!("Unknown division by zero behavior: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub dialect: Dialect,
+    /// Overrides the dialect's behavior for division by zero.
+    pub division_by_zero: Option<DivisionByZero>,
+    pub overflow: Overflow,
+    /// Seeds the directions taken by `?`, which are different on every run otherwise.
+    pub seed: Option<u64>,
+    /// The most steps `Jit::run` may execute, counting every cell the IP lands on. A compiled
+    /// block that would go over the limit is interpreted a step at a time instead, so the program
+    /// stops after exactly this many steps.
+    pub max_steps: Option<u64>,
+    /// How long the program may take, from the first time it's run. A program that's run a slice
+    /// at a time with `Jit::run_for` has one timeout for all of its slices, and the time between
+    /// them counts towards it.
+    pub timeout: Option<Duration>,
+    /// The most values the stack may hold.
+    pub max_stack: Option<usize>,
+    /// The most bytes of machine code that may be compiled for the blocks in use at once.
+    pub max_code_size: Option<usize>,
+    /// Run every instruction through `Jit::step`, rather than compiling anything.
+    pub interpret: bool,
+    /// Count how often each cell and trace is executed, in `Jit::profile`.
+    pub profile: bool,
+    /// How many times a block is interpreted before it's compiled, so that code which only runs
+    /// a few times isn't worth the cost of compiling. With the default of 10, the fixtures that
+    /// finish in under a millisecond run 3 to 13 times faster than when every block is compiled
+    /// straight away, and longer programs run no slower. 0 compiles every block the first time
+    /// it's reached.
+    pub compile_threshold: u32,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            dialect: Dialect::Befunge93,
+            division_by_zero: None,
+            overflow: Overflow::Wrap,
+            seed: None,
+            max_steps: None,
+            timeout: None,
+            max_stack: None,
+            max_code_size: None,
+            interpret: false,
+            profile: false,
+            compile_threshold: 10,
+        }
+    }
+}
+
+/// The result of running a program to completion.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The program executed `@`.
+    Terminated,
+    /// The program ran out of steps or time before finishing.
+    BudgetExhausted,
+    /// The program was stopped through its `CancelToken`.
+    Cancelled,
+    /// `Jit::run_for` used up its budget. Running again carries on from where the program paused.
+    Paused,
+    /// The IP reached a breakpoint, which it will execute next.
+    Breakpoint,
+    /// The program wrote to a watched cell, or its stack grew to a watched depth.
+    Watchpoint,
+}
+
+/// Where a program being debugged should stop.
+#[derive(Clone, Debug, Default)]
+pub struct Watches {
+    pub breakpoints: HashSet<space::Pos>,
+    pub cells: HashSet<space::Pos>,
+    pub stack_depth: Option<usize>,
+}
+
+/// Counts of how a program has been run so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub blocks_compiled: u64,
+    /// Passes through blocks that were interpreted because they weren't hot enough to compile.
+    pub blocks_interpreted: u64,
+    /// Steps run by the interpreter, which includes every branch and `p`.
+    pub steps_interpreted: u64,
+    /// Blocks read from funge space, whether to be interpreted or compiled.
+    pub blocks_traced: u64,
+    /// Bytes of machine code generated for compiled blocks.
+    pub code_bytes: u64,
+    /// Times `p` threw away compiled blocks.
+    pub invalidations: u64,
+    /// Every `?` run, whether it was interpreted or compiled code picked the direction.
+    pub random_turns: u64,
+    pub slow_paths: SlowPaths,
+    /// The most values the stack has held.
+    pub peak_stack_depth: usize,
+}
+
+impl Stats {
+    /// The counts laid out for people to read, a line each, after the number of `steps` the
+    /// program has run. This is what `--stats` prints.
+    pub fn report(&self, steps: u64) -> String {
+        format!(
+            "steps:              {}\n\
+             steps interpreted:  {}\n\
+             blocks traced:      {}\n\
+             blocks interpreted: {}\n\
+             blocks compiled:    {}\n\
+             code generated:     {} bytes\n\
+             invalidations:      {}\n\
+             random turns:       {}\n\
+             slow paths:\n\
+             \x20 _:                {}\n\
+             \x20 |:                {}\n\
+             \x20 ?:                {}\n\
+             \x20 p:                {}\n\
+             peak stack depth:   {}\n",
+            steps,
+            self.steps_interpreted,
+            self.blocks_traced,
+            self.blocks_interpreted,
+            self.blocks_compiled,
+            self.code_bytes,
+            self.invalidations,
+            self.random_turns,
+            self.slow_paths.horizontal_if,
+            self.slow_paths.vertical_if,
+            self.slow_paths.random,
+            self.slow_paths.put,
+            self.peak_stack_depth,
+        )
+    }
+}
+
+/// Instructions that compiled code can't run straight through, by the instruction.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SlowPaths {
+    /// `_`, which is always interpreted.
+    pub horizontal_if: u64,
+    /// `|`, which is always interpreted.
+    pub vertical_if: u64,
+    /// `?`, when it's interpreted, or when compiled code went in a direction that wasn't linked
+    /// to a compiled block yet.
+    pub random: u64,
+    /// `p`, which is always interpreted.
+    pub put: u64,
+}
+
+/// How far `Jit::run_for` runs before pausing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Budget {
+    /// Run for this many steps, counting every cell the IP lands on.
+    Steps(u64),
+    /// Run for this many traces, each of which is one pass through a compiled block, or a single
+    /// instruction that isn't compiled.
+    Traces(u64),
+}
+
 pub trait IO {
-    fn input_char(&mut self) -> Option<u8>;
-    fn input_number(&mut self) -> isize;
-    fn output_char(&mut self, c: u8);
-    fn output_number(&mut self, n: isize);
+    /// Read a single byte, or `None` at the end of the input.
+    fn input_char(&mut self) -> io::Result<Option<u8>>;
+    /// Read a number as `reader::Reader::read_number` does, or `None` at the end of the input.
+    fn input_number(&mut self) -> io::Result<Option<isize>>;
+    fn output_char(&mut self, c: u8) -> io::Result<()>;
+    fn output_number(&mut self, n: &dyn fmt::Display) -> io::Result<()>;
+
+    /// Write out any buffered output. Called when the program exits.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Input that's been read ahead but not used yet, such as the character after a number, in
+    /// the order `unread_input` takes it. This is saved along with the state of the program.
+    fn pending_input(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Put back input from `pending_input`, so that it's read before anything else.
+    fn unread_input(&mut self, _pending: &[u8]) {}
+
+    /// Ask the user for a number that's read from the input next, such as the result of dividing
+    /// by zero. Written to standard error by default, so that it stays out of the output.
+    fn prompt(&mut self, prompt: &str) -> io::Result<()> {
+        let mut stderr = io::stderr();
+        stderr.write_all(prompt.as_bytes())?;
+        stderr.flush()
+    }
 }
 
 pub struct StdIO {
-    input: std::io::Stdin,
-    output: std::io::Stdout,
+    input: reader::Reader<std::io::Stdin>,
+    output: io::BufWriter<std::io::Stdout>,
+    buffered: bool,
+}
+
+impl Default for StdIO {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StdIO {
+    /// Output is held back until a newline is written, input is requested, the program exits, or
+    /// `OUTPUT_BUFFER_SIZE` bytes are waiting.
+    pub const OUTPUT_BUFFER_SIZE: usize = 8192;
+
     pub fn new() -> Self {
         StdIO {
-            input: io::stdin(),
-            output: io::stdout(),
+            input: reader::Reader::new(io::stdin()),
+            output: io::BufWriter::with_capacity(Self::OUTPUT_BUFFER_SIZE, io::stdout()),
+            buffered: true,
         }
     }
-}
 
-impl IO for StdIO {
-    fn input_char(&mut self) -> Option<u8> {
-        let mut buf = [0; 1];
+    /// Flush after every character or number written, for interactive programs that print
+    /// partial lines.
+    pub fn unbuffered() -> Self {
+        StdIO {
+            buffered: false,
+            ..Self::new()
+        }
+    }
 
-        // NOTE: unwrap might cause issues here
-        if let Ok(()) = self.input.read_exact(&mut buf) {
-            Some(buf[0])
-        } else {
-            None
+    fn written(&mut self, newline: bool) -> io::Result<()> {
+        if !self.buffered || newline {
+            self.output.flush()?;
         }
+        Ok(())
     }
+}
 
-    fn input_number(&mut self) -> isize {
-        let mut text = String::new();
-        self.input
-            .read_line(&mut text)
-            .expect("Failed to read a line");
-        text.trim()
-            .parse::<isize>()
-            .expect("Failed to read a number")
+impl IO for StdIO {
+    fn input_char(&mut self) -> io::Result<Option<u8>> {
+        self.output.flush()?;
+        self.input.read_char()
     }
 
-    fn output_char(&mut self, c: u8) {
+    fn input_number(&mut self) -> io::Result<Option<isize>> {
+        self.output.flush()?;
+        self.input.read_number()
+    }
+
+    fn output_char(&mut self, c: u8) -> io::Result<()> {
         let buf = [c; 1];
-        self.output.write_all(&buf).unwrap();
-        self.output.flush().unwrap();
+        self.output.write_all(&buf)?;
+        self.written(c == b'\n')
+    }
+
+    fn output_number(&mut self, n: &dyn fmt::Display) -> io::Result<()> {
+        self.output.write_fmt(format_args!("{}", n))?;
+        self.written(false)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
     }
 
-    fn output_number(&mut self, n: isize) {
-        self.output.write_fmt(format_args!("{}", n)).unwrap();
-        self.output.flush().unwrap();
+    fn pending_input(&self) -> Vec<u8> {
+        self.input.pending().to_vec()
+    }
+
+    fn unread_input(&mut self, pending: &[u8]) {
+        for &c in pending {
+            self.input.unread(c);
+        }
+    }
+
+    fn prompt(&mut self, prompt: &str) -> io::Result<()> {
+        // show the output that led up to the question first
+        self.output.flush()?;
+        let mut stderr = io::stderr();
+        stderr.write_all(prompt.as_bytes())?;
+        stderr.flush()
     }
 }
 
-pub struct Jit<I: IO> {
-    pub cells: space::Funge93,
+pub struct Jit<I: IO, C: Cell = i64> {
+    pub cells: space::Funge93<C>,
     pub io: I,
-    pub stack: Vec<isize>,
+    pub stack: Vec<C>,
     pub pc: space::Pos,
     pub delta: space::Pos,
+    pub options: Options,
+    /// The error that stopped the most recently executed block.
+    pub error: Option<FunjitError>,
+    /// Chooses directions for `?`, unless `directions` is set.
+    pub rng: random::XorShift,
+    pub directions: Option<Box<dyn Directions + Send>>,
+    /// The number of steps executed so far.
+    pub steps: u64,
+    /// When `steps` exceeds this, `check_budget` is called.
+    check_at: u64,
+    deadline: Option<Instant>,
+    /// Why the most recently executed block stopped the program without an error.
+    pub stopped: Option<ExitStatus>,
+    cancel: CancelToken,
+    /// The number of traces executed while running for a `Budget::Traces`.
+    traces: u64,
+    pause_steps: Option<u64>,
+    pause_traces: Option<u64>,
+    /// Whether the interpreter is partway through a string.
+    string_mode: bool,
+    cache: Cache<I, C>,
+    pub stats: Stats,
+    watches: Watches,
+    tracer: Option<Tracer>,
+    dumper: Option<BlockDumper>,
+    perf: Option<Perf>,
+    debug_info: Option<DebugInfo>,
+    profile: Option<Profile>,
+    tape: Option<Tape>,
 }
 
-impl<I: IO> Jit<I> {
-    pub fn new(cells: space::Funge93, io: I) -> Self {
+impl<I: IO, C: Cell> Jit<I, C> {
+    pub fn new(cells: space::Funge93<C>, io: I) -> Self {
+        Self::with_options(cells, io, Options::default())
+    }
+
+    pub fn with_options(cells: space::Funge93<C>, io: I, options: Options) -> Self {
+        let rng = match options.seed {
+            Some(seed) => random::XorShift::new(seed),
+            None => random::XorShift::from_entropy(),
+        };
+
         Jit {
             cells,
             io,
             stack: Vec::new(),
             pc: space::Pos::new(0, 0),
             delta: space::Pos::new(1, 0),
+            options,
+            error: None,
+            rng,
+            directions: None,
+            steps: 0,
+            check_at: 0,
+            deadline: None,
+            stopped: None,
+            cancel: CancelToken::new(),
+            traces: 0,
+            pause_steps: None,
+            pause_traces: None,
+            string_mode: false,
+            cache: Cache::new(),
+            stats: Stats::default(),
+            watches: Watches::default(),
+            tracer: None,
+            dumper: None,
+            perf: None,
+            debug_info: None,
+            profile: None,
+            tape: None,
+        }
+    }
+
+    /// Replace the source of directions for `?`.
+    pub fn set_directions(&mut self, directions: Box<dyn Directions + Send>) {
+        // compiled blocks may step the generator inline rather than asking for a direction
+        self.clear_cache();
+        self.directions = Some(directions);
+    }
+
+    /// Stop the program when `token` is cancelled, instead of the token the `Jit` started with.
+    pub fn set_cancel_token(&mut self, token: CancelToken) {
+        // compiled blocks poll the flag of the token they were compiled with, which may be freed
+        self.clear_cache();
+        self.cancel = token;
+    }
+
+    /// A token that stops the program when it's cancelled, which can be sent to another thread.
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    pub fn watches(&self) -> &Watches {
+        &self.watches
+    }
+
+    /// Change where the program stops when it's run. Blocks that pass through a breakpoint are
+    /// interpreted rather than run as compiled code, and everything is interpreted while the stack
+    /// depth is watched.
+    pub fn set_watches(&mut self, watches: Watches) {
+        // compiled blocks may be linked to blocks that now have breakpoints
+        self.clear_cache();
+        self.watches = watches;
+    }
+
+    /// Record what the program does with `tracer`, or stop recording it.
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        // compiled blocks only report to the tracer if they were compiled with one
+        self.clear_cache();
+        self.tracer = tracer;
+    }
+
+    pub fn tracer(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Write out each block as it's compiled with `dumper`, or stop writing them out.
+    pub fn set_dumper(&mut self, dumper: Option<BlockDumper>) {
+        // blocks that were already compiled are compiled again, so that they're dumped too
+        self.clear_cache();
+        self.dumper = dumper;
+    }
+
+    /// Tell `perf` about each block as it's compiled, or stop telling it.
+    pub fn set_perf(&mut self, perf: Option<Perf>) {
+        self.clear_cache();
+        self.perf = perf;
+    }
+
+    /// How often each cell and trace has been executed, if the program has been run with
+    /// `Options::profile` set.
+    pub fn profile(&mut self) -> Option<&Profile> {
+        self.collect_profile();
+        self.profile.as_ref()
+    }
+
+    // Add the passes that compiled blocks have counted to the profile.
+    fn collect_profile(&mut self) {
+        if let Some(profile) = &mut self.profile {
+            for (&(pos, delta), block) in &mut self.cache.blocks {
+                if let Some(counter) = &mut block.counter {
+                    counter.collect(profile, pos, delta);
+                }
+            }
         }
     }
 
-    pub fn get(&mut self) -> isize {
+    // Throw away every compiled block, keeping what they've counted for the profile.
+    fn clear_cache(&mut self) {
+        self.collect_profile();
+        self.cache.clear();
+    }
+
+    /// Describe each block to GDB as it's compiled with `debug_info`, or stop describing them.
+    pub fn set_debug_info(&mut self, debug_info: Option<DebugInfo>) {
+        self.clear_cache();
+        self.debug_info = debug_info;
+    }
+
+    fn trace_level(&self) -> Option<TraceLevel> {
+        self.tracer.as_ref().map(|tracer| tracer.level)
+    }
+
+    /// Record the step at the given position and delta, which is `remaining` steps before the
+    /// end of the block being executed.
+    pub fn trace_step(&mut self, x: isize, y: isize, dx: isize, dy: isize, remaining: u64) -> bool {
+        let step = self.steps - remaining - 1;
+        self.traced(step, space::Pos::new(x, y), space::Pos::new(dx, dy), None)
+    }
+
+    /// Record a pass through the block of `steps` steps that starts at the given position and
+    /// delta, which have just been counted.
+    pub fn trace_block(&mut self, x: isize, y: isize, dx: isize, dy: isize, steps: u64) -> bool {
+        let step = self.steps - steps;
+        self.traced(step, space::Pos::new(x, y), space::Pos::new(dx, dy), Some(steps))
+    }
+
+    fn traced(
+        &mut self,
+        step: u64,
+        pos: space::Pos,
+        delta: space::Pos,
+        steps: Option<u64>,
+    ) -> bool {
+        match self.trace(step, pos, delta, steps) {
+            Ok(()) => true,
+            Err(err) => {
+                self.error = Some(err);
+                false
+            }
+        }
+    }
+
+    fn trace(
+        &mut self,
+        step: u64,
+        pos: space::Pos,
+        delta: space::Pos,
+        steps: Option<u64>,
+    ) -> Result<(), FunjitError> {
+        if let Some(tracer) = &mut self.tracer {
+            let instr = self.cells.instr(pos.x as usize, pos.y as usize);
+            let top = self.stack.last().map(|top| top as &dyn fmt::Display);
+            tracer.event(step, pos, delta, instr, top, steps).map_err(FunjitError::Trace)?;
+        }
+        Ok(())
+    }
+
+    // Record a block of `steps` steps that's about to be interpreted at `TraceLevel::Block`.
+    fn trace_interpreted(&mut self, steps: u64) -> Result<(), FunjitError> {
+        if self.trace_level() == Some(TraceLevel::Block) {
+            self.trace(self.steps, self.pc, self.delta, Some(steps))?;
+        }
+        Ok(())
+    }
+
+    /// Record everything the program reads and every direction `?` takes from here on, so that
+    /// it can be replayed after being restored to a `Snapshot`.
+    pub fn start_recording(&mut self) {
+        if self.tape.is_none() {
+            // directions are only recorded when `?` calls out to `random_direction`
+            self.clear_cache();
+            self.tape = Some(Tape::default());
+        }
+    }
+
+    pub fn tape(&self) -> Option<&Tape> {
+        self.tape.as_ref()
+    }
+
+    /// The state of the program as it is now.
+    pub fn snapshot(&self) -> Snapshot<C> {
+        Snapshot {
+            steps: self.steps,
+            cells: self.cells.clone(),
+            stack: self.stack.clone(),
+            pc: self.pc,
+            delta: self.delta,
+            string_mode: self.string_mode,
+            tape: self.tape.as_ref().map(|tape| tape.cursor).unwrap_or_default(),
+        }
+    }
+
+    /// Go back to the state in `snapshot`. If the program is being recorded, running it from there
+    /// replays what it read and the directions it took.
+    pub fn restore(&mut self, snapshot: &Snapshot<C>) {
+        // the cells may have been changed by `p` since
+        self.clear_cache();
+        self.cache.pending_link = None;
+
+        self.steps = snapshot.steps;
+        self.cells = snapshot.cells.clone();
+        self.stack = snapshot.stack.clone();
+        self.pc = snapshot.pc;
+        self.delta = snapshot.delta;
+        self.string_mode = snapshot.string_mode;
+        if let Some(tape) = &mut self.tape {
+            tape.cursor = snapshot.tape;
+        }
+        self.error = None;
+        self.stopped = None;
+    }
+
+    /// How many steps to take between checks of the deadline.
+    const CHECK_INTERVAL: u64 = 1 << 16;
+
+    /// Count `steps` that are about to be executed, returning false if there's no budget left for
+    /// them.
+    fn charge(&mut self, steps: u64) -> bool {
+        self.steps += steps;
+        (self.steps <= self.check_at && !self.cancel.is_cancelled()) || self.check_budget(steps)
+    }
+
+    /// Called once `steps` passes `check_at` or the program is cancelled, with the steps that were
+    /// just counted for the block or instruction about to run. If they can't be run they're
+    /// uncounted, and the reason for stopping is recorded in `stopped`, unless it's a step limit
+    /// that `step_limit_status` reports.
+    pub fn check_budget(&mut self, steps: u64) -> bool {
+        let over_steps = self.options.max_steps.is_some_and(|max| self.steps > max)
+            || self.pause_steps.is_some_and(|target| self.steps > target)
+            || self.pause_traces.is_some_and(|target| self.traces >= target);
+        let stopped = if self.cancel.is_cancelled() {
+            Some(ExitStatus::Cancelled)
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Some(ExitStatus::BudgetExhausted)
+        } else {
+            None
+        };
+
+        if over_steps || stopped.is_some() {
+            self.steps -= steps;
+            self.stopped = stopped;
+            return false;
+        }
+
+        self.check_at = u64::MAX;
+        if self.pause_traces.is_some() {
+            // traces are only counted here, so every one has to come through
+            self.traces += 1;
+            self.check_at = 0;
+        }
+        if self.deadline.is_some() {
+            self.check_at = self.check_at.min(self.steps.saturating_add(Self::CHECK_INTERVAL));
+        }
+        if let Some(max) = self.options.max_steps {
+            self.check_at = self.check_at.min(max);
+        }
+        if let Some(target) = self.pause_steps {
+            self.check_at = self.check_at.min(target);
+        }
+
+        true
+    }
+
+    // How the program stops if it's used up the steps or traces it's allowed.
+    fn step_limit_status(&self) -> Option<ExitStatus> {
+        if self.options.max_steps.is_some_and(|max| self.steps >= max) {
+            Some(ExitStatus::BudgetExhausted)
+        } else if self.pause_steps.is_some_and(|target| self.steps >= target)
+            || self.pause_traces.is_some_and(|target| self.traces >= target)
+        {
+            Some(ExitStatus::Paused)
+        } else {
+            None
+        }
+    }
+
+    /// Choose a direction for `?`, as an index into `random::DIRECTIONS`.
+    pub fn random_direction(&mut self) -> usize {
+        let (directions, rng) = (&mut self.directions, &mut self.rng);
+        let mut choose = move || match directions {
+            Some(directions) => random::direction_index(directions.next_direction()),
+            None => (rng.next_u64() >> 62) as usize,
+        };
+
+        match &mut self.tape {
+            Some(tape) => tape.direction(choose),
+            None => choose(),
+        }
+    }
+
+    // Read a number, or a character if `number` isn't set, replaying it from the tape if there is
+    // one. The prompt is only shown when the input is really read.
+    fn read_input(&mut self, number: bool, prompt: Option<&str>) -> io::Result<Option<isize>> {
+        let io = &mut self.io;
+        let mut read = move || {
+            if let Some(prompt) = prompt {
+                io.prompt(prompt)?;
+            }
+            if number {
+                io.input_number()
+            } else {
+                io.input_char().map(|c| c.map(isize::from))
+            }
+        };
+
+        match &mut self.tape {
+            Some(tape) => tape.input(read),
+            None => read(),
+        }
+    }
+
+    // Whether output should be written, rather than having been written before the program was
+    // restored to an earlier snapshot.
+    fn writes_output(&mut self) -> bool {
+        self.tape.as_mut().is_none_or(Tape::output)
+    }
+
+    /// Move off the `?` at the given position in the direction with the given index.
+    pub fn turn(&mut self, x: isize, y: isize, index: usize) {
+        self.delta = random::DIRECTIONS[index];
+        self.pc = space::Pos::new(x, y);
+        self.pc.move_by(&self.delta);
+    }
+
+    pub fn get(&mut self) -> bool {
         let y = self.pop();
         let x = self.pop();
-        if y >= 0
-            && y < space::Funge93::HEIGHT as isize
+        let val = if y >= 0
+            && y < space::HEIGHT as isize
             && x >= 0
-            && x < space::Funge93::WIDTH as isize
+            && x < space::WIDTH as isize
         {
-            self.cells.get(x as usize, y as usize) as isize
+            self.cells.get(x as usize, y as usize).clone()
         } else {
-            0
-        }
+            C::default()
+        };
+        self.push_cell(val)
     }
 
     pub fn put(&mut self) {
         let y = self.pop();
         let x = self.pop();
-        let v = self.pop();
+        let v = self.pop_cell();
         if y >= 0
-            && y < space::Funge93::HEIGHT as isize
+            && y < space::HEIGHT as isize
             && x >= 0
-            && x < space::Funge93::WIDTH as isize
+            && x < space::WIDTH as isize
         {
-            self.cells.set(x as usize, y as usize, v as u8);
+            self.cells.set(x as usize, y as usize, v);
+        }
+    }
+
+    /// Push a value, or record an error if the stack is already as deep as it's allowed to be.
+    pub fn push(&mut self, val: isize) -> bool {
+        self.push_cell(C::from_isize(val))
+    }
+
+    pub fn push_cell(&mut self, val: C) -> bool {
+        if let Some(max) = self.options.max_stack {
+            if self.stack.len() >= max {
+                self.error = Some(FunjitError::LimitExceeded(Limit::StackDepth(max)));
+                return false;
+            }
         }
+
+        self.stack.push(val);
+        self.stats.peak_stack_depth = self.stats.peak_stack_depth.max(self.stack.len());
+        true
     }
 
-    pub fn push(&mut self, val: isize) {
-        self.stack.push(val)
+    /// Move the pc and delta to where the interpreter would leave them, when the compiled block
+    /// entered at `x`, `y` moving `dx`, `dy` has stopped with an error after `executed` steps.
+    pub fn leave_block(&mut self, executed: u64, x: isize, y: isize, dx: isize, dy: isize) {
+        // the cell that failed counts as executed, unless it was its trace that failed
+        let step = match &self.error {
+            None => return,
+            Some(FunjitError::Trace(_)) => executed,
+            Some(_) => executed.saturating_sub(1),
+        };
+        let key = (space::Pos::new(x, y), space::Pos::new(dx, dy));
+        if let Some(origin) = self.cache.blocks.get(&key).and_then(|block| {
+            block.origins.get(step as usize)
+        }) {
+            self.pc = origin.pos;
+            self.delta = origin.delta;
+        }
     }
 
     pub fn set_pc(&mut self, x: isize, y: isize) {
@@ -369,61 +1576,181 @@ impl<I: IO> Jit<I> {
         self.delta.y = y;
     }
 
-    pub fn input(&mut self) {
-        if let Some(c) = self.io.input_char() {
-            self.push(c as isize)
-        } else {
-            self.push(-1);
+    pub fn input(&mut self, x: isize, y: isize, dx: isize, dy: isize) -> bool {
+        match self.read_input(false, None) {
+            Ok(Some(c)) => self.push(c),
+            Ok(None) => self.end_of_input(x, y, dx, dy),
+            Err(err) => self.io_error(x, y, err),
         }
     }
 
-    pub fn output(&mut self) {
+    pub fn output(&mut self, x: isize, y: isize) -> bool {
         let val = self.pop();
-        self.io.output_char(val as u8);
+        if !self.writes_output() {
+            return true;
+        }
+        match self.io.output_char(val as u8) {
+            Ok(()) => true,
+            Err(err) => self.io_error(x, y, err),
+        }
     }
 
-    pub fn input_number(&mut self) {
-        let num = self.io.input_number();
-        self.push(num);
+    pub fn input_number(&mut self, x: isize, y: isize, dx: isize, dy: isize) -> bool {
+        match self.read_input(true, None) {
+            Ok(Some(num)) => self.push(num),
+            Ok(None) => self.end_of_input(x, y, dx, dy),
+            Err(err) => self.io_error(x, y, err),
+        }
     }
 
-    pub fn output_number(&mut self) {
-        let val = self.pop();
-        self.io.output_number(val);
+    /// Befunge-93 pushes -1 when input runs out, while Befunge-98 reflects the IP.
+    fn end_of_input(&mut self, x: isize, y: isize, dx: isize, dy: isize) -> bool {
+        match self.options.dialect {
+            Dialect::Befunge93 => self.push(-1),
+            Dialect::Befunge98 => {
+                self.delta = space::Pos::new(-dx, -dy);
+                self.pc = space::Pos::new(x, y);
+                self.pc.move_by(&self.delta);
+                false
+            }
+        }
     }
 
-    pub fn pop(&mut self) -> isize {
-        if let Some(val) = self.stack.pop() {
-            val
-        } else {
-            0
+    pub fn output_number(&mut self, x: isize, y: isize) -> bool {
+        let val = self.pop_cell();
+        if !self.writes_output() {
+            return true;
         }
+        match self.io.output_number(&val) {
+            Ok(()) => true,
+            Err(err) => self.io_error(x, y, err),
+        }
+    }
+
+    fn io_error(&mut self, x: isize, y: isize, err: io::Error) -> bool {
+        self.error = Some(FunjitError::Io(space::Pos::new(x, y), err));
+        false
+    }
+
+    pub fn pop(&mut self) -> isize {
+        self.pop_cell().to_isize()
+    }
+
+    pub fn pop_cell(&mut self) -> C {
+        self.stack.pop().unwrap_or_default()
     }
 
     pub fn peek(&mut self) -> isize {
         if let Some(val) = self.stack.last() {
-            *val
+            val.to_isize()
         } else {
             0
         }
     }
 
+    pub fn dup(&mut self) -> bool {
+        let val = self.stack.last().cloned().unwrap_or_default();
+        self.push_cell(val)
+    }
+
+    pub fn swap(&mut self) -> bool {
+        let b = self.pop_cell();
+        let a = self.pop_cell();
+        self.push_cell(b) && self.push_cell(a)
+    }
+
+    pub fn not(&mut self) -> bool {
+        let val = self.pop_cell();
+        self.push(val.is_zero() as isize)
+    }
+
+    pub fn greater(&mut self) -> bool {
+        let b = self.pop_cell();
+        let a = self.pop_cell();
+        self.push((a > b) as isize)
+    }
+
+    /// Pop two values and push the result of applying `op` to them, for the instruction at the
+    /// given position.
+    pub fn arith(&mut self, x: isize, y: isize, op: Arith) -> bool {
+        let b = self.pop_cell();
+        let a = self.pop_cell();
+        self.apply(x, y, op, a, b)
+    }
+
+    /// Push the result of an operation that compiled code couldn't complete inline.
+    pub fn arith_slow(&mut self, x: isize, y: isize, op: Arith, a: isize, b: isize) -> bool {
+        self.apply(x, y, op, C::from_isize(a), C::from_isize(b))
+    }
+
+    fn apply(&mut self, x: isize, y: isize, op: Arith, a: C, b: C) -> bool {
+        if (op == Arith::Div || op == Arith::Rem) && b.is_zero() {
+            return self.divide_cell_by_zero(x, y, op, &a);
+        }
+
+        if let Some(val) = a.arith(op, &b, self.options.overflow) {
+            self.push_cell(val)
+        } else {
+            self.error = Some(FunjitError::Overflow(space::Pos::new(x, y)));
+            false
+        }
+    }
+
+    /// Push the result of dividing `a` by zero with `op` at the given position, or record an
+    /// error if the program should stop.
+    pub fn divide_by_zero(&mut self, x: isize, y: isize, op: Arith, a: isize) -> bool {
+        self.divide_cell_by_zero(x, y, op, &C::from_isize(a))
+    }
+
+    fn divide_cell_by_zero(&mut self, x: isize, y: isize, op: Arith, a: &C) -> bool {
+        let behavior = self
+            .options
+            .division_by_zero
+            .unwrap_or_else(|| self.options.dialect.division_by_zero());
+
+        match behavior {
+            DivisionByZero::Ask => {
+                let symbol = if op == Arith::Rem { '%' } else { '/' };
+                let prompt = format!("What do you want {}{}0 to be? ", a, symbol);
+                match self.read_input(true, Some(&prompt)) {
+                    Ok(num) => self.push(num.unwrap_or(-1)),
+                    Err(err) => self.io_error(x, y, err),
+                }
+            }
+            DivisionByZero::Zero => self.push(0),
+            DivisionByZero::Trap => {
+                self.error = Some(FunjitError::DivisionByZero(space::Pos::new(x, y)));
+                false
+            }
+        }
+    }
+
     // Returns basic blocks from the funge space
-    pub fn next_block(space: &space::Funge93, mut pc: space::Pos, mut delta: space::Pos) -> Block {
+    pub fn next_block(space: &space::Funge93<C>, mut pc: space::Pos, mut delta: space::Pos) -> Block {
         let mut block = Block::default();
+        let start = (pc, delta);
         let mut seen = HashSet::new();
         let mut string_mode = false;
 
         loop {
-            match space.get(pc.x as usize, pc.y as usize) {
+            let here = Origin { pos: pc, delta, step: block.steps };
+            match space.instr(pc.x as usize, pc.y as usize) {
                 c if string_mode => {
                     if c == b'"' {
                         string_mode = !string_mode
                     }
-                    block.code.push(c as char)
+                    block.code.push(c as char);
+                    block.origins.push(Origin { pos: pc, delta, step: block.steps });
                 }
 
-                b'_' | b'|' | b'?' => break,
+                b'_' | b'|' => break,
+
+                b'?' => {
+                    block.random = true;
+                    block.steps += 1;
+                    block.cells.push(here);
+                    break;
+                }
 
                 b'p' => {
                     block.mutates = true;
@@ -432,6 +1759,8 @@ impl<I: IO> Jit<I> {
 
                 b'@' => {
                     block.terminates = true;
+                    block.steps += 1;
+                    block.cells.push(here);
                     break;
                 }
 
@@ -448,18 +1777,28 @@ impl<I: IO> Jit<I> {
                     if c == b'"' {
                         string_mode = !string_mode
                     }
-                    block.code.push(c as char)
+                    block.code.push(c as char);
+                    block.origins.push(Origin { pos: pc, delta, step: block.steps });
                 }
             }
 
+            block.steps += 1;
+            block.cells.push(here);
             pc.move_by(&delta);
 
-            if seen.contains(&pc) {
+            // coming back to the start of the block makes it a loop, but a cycle that starts
+            // further along belongs to the block that starts where it does
+            if (pc, delta) == start && !string_mode {
                 block.loops = true;
                 break;
             }
 
-            seen.insert(pc);
+            // compiled code doesn't hand string mode back to the interpreter, so a block can't end
+            // inside a string. A string always ends, at the latest when it wraps back around to
+            // the `"` that started it
+            if !string_mode && !seen.insert((pc, delta)) {
+                break;
+            }
         }
 
         block.delta = delta;
@@ -468,63 +1807,356 @@ impl<I: IO> Jit<I> {
         block
     }
 
-    pub fn run(&mut self) {
-        let mut blocks: HashMap<space::Pos, CompiledBlock<I>> = HashMap::new();
+    pub fn run(&mut self) -> Result<ExitStatus, FunjitError> {
+        // the clock starts on the first run, and keeps going between the slices of `run_for`
+        if self.deadline.is_none() {
+            self.deadline = self.options.timeout.map(|timeout| Instant::now() + timeout);
+        }
+        self.check_at = 0;
+        if self.options.profile && self.profile.is_none() {
+            self.profile = Some(Profile::default());
+        }
+
+        let result = self.execute();
+
+        // output is flushed even when the program fails, so that it's clear how far it got
+        let flushed = self.io.flush();
+        let traced = self.tracer.as_mut().map_or(Ok(()), Tracer::flush);
+        let dumped = self.dumper.as_mut().map_or(Ok(()), BlockDumper::flush);
+        let status = result?;
+        flushed.map_err(|err| FunjitError::Io(self.pc, err))?;
+        traced.map_err(FunjitError::Trace)?;
+        dumped.map_err(FunjitError::Dump)?;
+
+        Ok(status)
+    }
+
+    /// Run until the program stops or `budget` is used up, returning `ExitStatus::Paused` in the
+    /// latter case. The `Jit` can be run again afterwards to carry on.
+    pub fn run_for(&mut self, budget: Budget) -> Result<ExitStatus, FunjitError> {
+        match budget {
+            Budget::Steps(steps) => self.pause_steps = Some(self.steps.saturating_add(steps)),
+            Budget::Traces(traces) => self.pause_traces = Some(self.traces.saturating_add(traces)),
+        }
+
+        let result = self.run();
+        self.pause_steps = None;
+        self.pause_traces = None;
+        result
+    }
+
+    /// Execute the instruction at pc and move on, without compiling anything. Returns how the
+    /// program stopped, if it did. This is the reference for what compiled code does, and the
+    /// generated tests check that the two agree.
+    pub fn step(&mut self) -> Result<Option<ExitStatus>, FunjitError> {
+        if !self.charge(1) {
+            return Ok(self.stopped.take().or_else(|| self.step_limit_status()));
+        }
+        if self.trace_level() == Some(TraceLevel::Instruction) {
+            if let Err(err) = self.trace(self.steps - 1, self.pc, self.delta, None) {
+                self.steps -= 1;
+                return Err(err);
+            }
+        }
+        self.stats.steps_interpreted += 1;
+        if let Some(profile) = &mut self.profile {
+            profile.step(self.pc);
+        }
+
+        let (x, y) = (self.pc.x, self.pc.y);
+        let (dx, dy) = (self.delta.x, self.delta.y);
+        let c = self.cells.instr(x as usize, y as usize);
+        let depth = self.stack.len();
+        let mut watched = false;
+
+        let ok = match c {
+            b'"' => {
+                self.string_mode = !self.string_mode;
+                true
+            }
+            c if self.string_mode => self.push(c as isize),
+
+            b'0'..=b'9' => self.push((c - b'0') as isize),
+            b'+' => self.arith(x, y, Arith::Add),
+            b'-' => self.arith(x, y, Arith::Sub),
+            b'*' => self.arith(x, y, Arith::Mul),
+            b'/' => self.arith(x, y, Arith::Div),
+            b'%' => self.arith(x, y, Arith::Rem),
+            b'!' => self.not(),
+            b'`' => self.greater(),
+            b':' => self.dup(),
+            b'\\' => self.swap(),
+            b'$' => {
+                self.pop();
+                true
+            }
+
+            b'.' => self.output_number(x, y),
+            b',' => self.output(x, y),
+            b'&' => self.input_number(x, y, dx, dy),
+            b'~' => self.input(x, y, dx, dy),
+
+            b'g' => self.get(),
+            b'p' => {
+                if !self.cache.blocks.is_empty() {
+                    self.stats.invalidations += 1;
+                }
+                self.clear_cache();
+                // the stack is peeked at rather than popped, to see where `put` will write
+                let stack = &self.stack;
+                let peek = |n| stack.len().checked_sub(n).map_or(0, |i| stack[i].to_isize());
+                watched = self.watches.cells.contains(&space::Pos::new(peek(2), peek(1)));
+                self.put();
+                true
+            }
+
+            b'_' => {
+                self.delta = if self.pop() == 0 {
+                    space::Pos::east()
+                } else {
+                    space::Pos::west()
+                };
+                true
+            }
+            b'|' => {
+                self.delta = if self.pop() == 0 {
+                    space::Pos::south()
+                } else {
+                    space::Pos::north()
+                };
+                true
+            }
+            b'?' => {
+                self.stats.slow_paths.random += 1;
+                self.stats.random_turns += 1;
+                self.delta = random::DIRECTIONS[self.random_direction()];
+                true
+            }
+            b'^' => {
+                self.delta = space::Pos::north();
+                true
+            }
+            b'>' => {
+                self.delta = space::Pos::east();
+                true
+            }
+            b'v' => {
+                self.delta = space::Pos::south();
+                true
+            }
+            b'<' => {
+                self.delta = space::Pos::west();
+                true
+            }
+            b'#' => {
+                self.pc.move_by(&self.delta);
+                true
+            }
+
+            b'@' => return Ok(Some(ExitStatus::Terminated)),
+
+            _ => true,
+        };
+
+        if !ok {
+            // either an error, or the IP has already been moved
+            return match self.error.take() {
+                Some(err) => Err(err),
+                None => Ok(None),
+            };
+        }
+
+        self.pc.move_by(&self.delta);
+
+        if let Some(watched_depth) = self.watches.stack_depth {
+            watched |= depth < watched_depth && self.stack.len() >= watched_depth;
+        }
+        if watched {
+            return Ok(Some(ExitStatus::Watchpoint));
+        }
+
+        Ok(None)
+    }
+
+    fn execute(&mut self) -> Result<ExitStatus, FunjitError> {
+        // a breakpoint where the program is resumed has already been hit
+        let start = self.steps;
 
         loop {
-            // at this point we should be at a control instruction, so update delta and take a step
-            // to find the next sequence.
-            match self.cells.get(self.pc.x as usize, self.pc.y as usize) {
-                b'|' => {
-                    if self.pop() == 0 {
-                        self.delta = space::Pos::south();
-                    } else {
-                        self.delta = space::Pos::north();
-                    }
+            if let Some(status) = self.step_limit_status() {
+                return Ok(status);
+            }
+            if self.steps != start && self.watches.breakpoints.contains(&self.pc) {
+                return Ok(ExitStatus::Breakpoint);
+            }
+
+            let link = self.cache.pending_link.take();
+
+            // branches and `p` aren't compiled, and neither is the rest of a string the
+            // interpreter has started
+            let instr = self.cells.instr(self.pc.x as usize, self.pc.y as usize);
+            if self.options.interpret
+                || self.string_mode
+                || self.watches.stack_depth.is_some()
+                || matches!(instr, b'_' | b'|' | b'p')
+            {
+                match instr {
+                    b'_' => self.stats.slow_paths.horizontal_if += 1,
+                    b'|' => self.stats.slow_paths.vertical_if += 1,
+                    b'p' => self.stats.slow_paths.put += 1,
+                    _ => (),
                 }
+                self.trace_interpreted(1)?;
+                if let Some(status) = self.step()? {
+                    return Ok(status);
+                }
+                continue;
+            }
 
-                b'_' => {
-                    if self.pop() == 0 {
-                        self.delta = space::Pos::east()
-                    } else {
-                        self.delta = space::Pos::west()
+            // a block that would overshoot a step limit is interpreted instead
+            let limit = match (self.options.max_steps, self.pause_steps) {
+                (Some(max), Some(target)) => Some(max.min(target)),
+                (max, target) => max.or(target),
+            };
+
+            // blocks are interpreted until they've been run `compile_threshold` times
+            let key = (self.pc, self.delta);
+            if !self.cache.blocks.contains_key(&key) {
+                let cells = &self.cells;
+                let stats = &mut self.stats;
+                let (count, block) = self.cache.cold.entry(key).or_insert_with(|| {
+                    stats.blocks_traced += 1;
+                    (0, Self::next_block(cells, key.0, key.1))
+                });
+
+                let steps = block.steps;
+                if *count < self.options.compile_threshold {
+                    *count += 1;
+                    self.stats.blocks_interpreted += 1;
+                    if let Some(profile) = &mut self.profile {
+                        profile.pass(key.0, key.1, steps, 1);
                     }
+                    self.trace_interpreted(steps)?;
+                    for i in 0..steps {
+                        if i > 0 && self.watches.breakpoints.contains(&self.pc) {
+                            return Ok(ExitStatus::Breakpoint);
+                        }
+                        if let Some(status) = self.step()? {
+                            return Ok(status);
+                        }
+                    }
+                    continue;
                 }
 
-                b'?' => match rand::random::<usize>() % 4 {
-                    0 => self.delta = space::Pos::north(),
-                    1 => self.delta = space::Pos::east(),
-                    2 => self.delta = space::Pos::south(),
-                    _ => self.delta = space::Pos::west(),
-                },
-
-                b'p' => {
-                    blocks.clear();
-                    self.put();
+                // and isn't compiled until it can be run in full, so that stepping through a
+                // program doesn't fill the cache with code that never runs
+                if limit.is_some_and(|limit| self.steps + steps > limit) {
+                    self.trace_interpreted(1)?;
+                    if let Some(status) = self.step()? {
+                        return Ok(status);
+                    }
+                    continue;
                 }
+            }
 
-                // everything else should be compiled
-                _ => {
-                    if !blocks.contains_key(&self.pc) {
-                        // NOTE: there's no special handling for when the blocks are empty, as the
-                        // compiled function will end up setting the pc and delta. This happens
-                        // when a block is made up entirely of instructions that change the
-                        // direction of the cursor, or whitespace.
-                        let block = Self::next_block(&self.cells, self.pc, self.delta);
-                        blocks.insert(self.pc, block.compile());
+            // NOTE: there's no special handling for when the blocks are empty, as the compiled
+            // function will end up setting the pc and delta. This happens when a block is made up
+            // entirely of instructions that change the direction of the cursor, or whitespace.
+            let inline_rng = self.directions.is_none() && self.tape.is_none();
+            let trace = self.trace_level();
+            let Cache {
+                blocks,
+                successors,
+                code_size,
+                cold,
+                ..
+            } = &mut self.cache;
+            let compiled_block = match blocks.entry((self.pc, self.delta)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    // the block was traced when it was first reached, and is compiled as it was
+                    let block = match cold.remove(&key) {
+                        Some((_, block)) => block,
+                        None => {
+                            self.stats.blocks_traced += 1;
+                            Self::next_block(&self.cells, self.pc, self.delta)
+                        }
+                    };
+                    let next = block.random.then(|| &**successors.entry(block.pc).or_default());
+                    let mut compiled = block.compile(
+                        &self.options,
+                        next,
+                        inline_rng,
+                        self.cancel.flag(),
+                        trace,
+                    );
+
+                    if let Some(dumper) = &mut self.dumper {
+                        dumper.dump(&block, &compiled).map_err(FunjitError::Dump)?;
+                    }
+                    if let Some(perf) = &mut self.perf {
+                        perf.load(&block, &compiled).map_err(FunjitError::Perf)?;
+                    }
+                    if let Some(debug_info) = &self.debug_info {
+                        compiled.registration = Some(debug_info.register(&block, &compiled));
                     }
 
-                    let compiled_block = blocks.get(&self.pc).unwrap();
-                    if compiled_block.run(self) {
-                        break;
+                    *code_size += compiled.size();
+                    self.stats.blocks_compiled += 1;
+                    self.stats.code_bytes += compiled.size() as u64;
+                    if let Some(max) = self.options.max_code_size {
+                        if *code_size > max {
+                            return Err(FunjitError::LimitExceeded(Limit::CodeSize(max)));
+                        }
                     }
 
-                    // no need to update pc, the compiled function does that
-                    continue;
+                    entry.insert(compiled)
+                }
+            };
+
+            // blocks with breakpoints are never linked, so that they can be stepped through
+            let stepped = compiled_block.touches(&self.watches.breakpoints);
+            if let Some((from, index)) = link.filter(|_| !stepped) {
+                if let Some(next) = successors.get_mut(&from) {
+                    next[index] = compiled_block.addr();
+                }
+            }
+
+            // the block is only borrowed from the cache, which isn't touched until it returns
+            let code = compiled_block.code;
+
+            let steps = self.steps + compiled_block.steps();
+            if stepped || limit.is_some_and(|limit| steps > limit) {
+                self.trace_interpreted(1)?;
+                if let Some(status) = self.step()? {
+                    return Ok(status);
                 }
+                continue;
             }
 
-            self.pc.move_by(&self.delta);
+            match BlockExit::from_code(code(self)) {
+                // no need to update pc, the compiled function does that
+                BlockExit::Continue => continue,
+                BlockExit::Terminate => return Ok(ExitStatus::Terminated),
+                BlockExit::Leave => {
+                    if let Some(err) = self.error.take() {
+                        return Err(err);
+                    }
+                    if let Some(status) = self.stopped.take() {
+                        return Ok(status);
+                    }
+                    // otherwise the block went over a step limit, or moved the pc, and the top of
+                    // the loop works out what to do next
+                    continue;
+                }
+                BlockExit::Unlinked => {
+                    self.stats.slow_paths.random += 1;
+                    let mut from = self.pc;
+                    from.move_by(&space::Pos::new(-self.delta.x, -self.delta.y));
+                    self.cache.pending_link = Some((from, random::direction_index(self.delta)));
+                    continue;
+                }
+            }
         }
     }
 }
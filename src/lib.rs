@@ -0,0 +1,47 @@
+//! A JIT compiler for Befunge. Programs are run through a `Builder`, which sets up a `jit::Jit`
+//! from program source and options:
+//!
+//! ```no_run
+//! let result = funjit::Builder::new(">25*\"!olleH\",,,,,,,@").max_steps(1_000_000).run();
+//! result.result.unwrap();
+//! ```
+
+extern crate dynasm;
+extern crate dynasmrt;
+extern crate gimli;
+extern crate iced_x86;
+extern crate libc;
+extern crate num_bigint;
+extern crate num_traits;
+extern crate object;
+extern crate rand;
+extern crate serde;
+extern crate serde_json;
+
+#[cfg(test)]
+pub mod test {
+    include!(concat!(env!("OUT_DIR"), "/exp_tests.rs"));
+}
+
+pub mod builder;
+pub mod cancel;
+pub mod cell;
+pub mod debugger;
+pub mod dump;
+pub mod error;
+pub mod gdb;
+pub mod history;
+pub mod jit;
+pub mod perf;
+pub mod profile;
+pub mod random;
+pub mod reader;
+pub mod space;
+pub mod state;
+pub mod trace;
+pub mod visualizer;
+
+pub use builder::{Builder, RunResult};
+pub use cancel::CancelToken;
+pub use error::FunjitError;
+pub use jit::{ExitStatus, Jit, IO};
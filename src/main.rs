@@ -1,34 +1,385 @@
-
 extern crate anyhow;
 extern crate clap;
-extern crate rand;
-extern crate dynasm;
-extern crate dynasmrt;
+extern crate funjit;
+extern crate num_bigint;
+
+use std::io::{self, prelude::*};
+
+use clap::{App, AppSettings, Arg, SubCommand};
+
+use funjit::cell::{self, Cell};
+use funjit::debugger::Debugger;
+use funjit::dump::BlockDumper;
+use funjit::gdb::DebugInfo;
+use funjit::perf::Perf;
+use funjit::profile::ReportFormat;
+use funjit::jit::{Jit, IO};
+use funjit::visualizer::{PaneIO, Visualizer};
+use funjit::trace::Tracer;
+use funjit::{jit, random, state, Builder, ExitStatus};
+
+enum Mode {
+    Run {
+        stats: bool,
+        /// Where to save the state of a program that stops without finishing.
+        save_state: Option<String>,
+        profile: Option<Profiling>,
+    },
+    Debug,
+    /// Animate the program, taking this many steps a second.
+    Visualize { speed: f64 },
+}
+
+/// How to report on a profiled program.
+struct Profiling {
+    /// Draw a heatmap on standard error.
+    heatmap: bool,
+    /// Write a report to this file.
+    report: Option<String>,
+    format: ReportFormat,
+}
+
+fn run_in<C: Cell>(
+    builder: Builder,
+    mode: Mode,
+    load_state: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    match mode {
+        Mode::Run { stats, save_state, profile } => {
+            run::<C>(builder, load_state, stats, save_state.as_deref(), profile)
+        }
+        Mode::Debug => run_debugger::<C>(builder, load_state),
+        Mode::Visualize { speed } => run_visualizer::<C>(builder, load_state, speed),
+    }
+}
+
+// Set up the program, carrying on from a saved state if there is one.
+fn build<I: IO, C: Cell>(
+    builder: Builder<I>,
+    load_state: Option<&str>,
+) -> Result<Jit<I, C>, anyhow::Error> {
+    let mut jit = builder.cells::<C>().build();
+    if let Some(path) = load_state {
+        state::load(&mut jit, std::fs::File::open(path)?)?;
+    }
+    Ok(jit)
+}
+
+fn run<C: Cell>(
+    builder: Builder,
+    load_state: Option<&str>,
+    stats: bool,
+    save_state: Option<&str>,
+    profiling: Option<Profiling>,
+) -> Result<(), anyhow::Error> {
+    let mut jit = build::<_, C>(builder, load_state)?;
+    let result = jit.run();
+    if let Some(profiling) = profiling {
+        report_profile(&mut jit, profiling)?;
+    }
+    if stats {
+        eprint!("{}", jit.stats.report(jit.steps));
+    }
+
+    let unfinished = matches!(result, Ok(ExitStatus::BudgetExhausted | ExitStatus::Cancelled));
+    if let Some(path) = save_state.filter(|_| unfinished) {
+        state::save(&jit, std::fs::File::create(path)?)?;
+    }
+
+    exit_status(result?, jit.steps)
+}
+
+fn report_profile<C: Cell>(
+    jit: &mut Jit<jit::StdIO, C>,
+    profiling: Profiling,
+) -> Result<(), anyhow::Error> {
+    let profile = match jit.profile() {
+        Some(profile) => profile.clone(),
+        None => return Ok(()),
+    };
+    let cells = &jit.cells;
+    if profiling.heatmap {
+        eprint!("{}", profile.heatmap(cells));
+    }
+    if let Some(path) = profiling.report {
+        let file = io::BufWriter::new(std::fs::File::create(path)?);
+        profile.write_report(cells, profiling.format, file)?;
+    }
+    Ok(())
+}
 
-#[cfg(test)]
-pub mod test {
-    include!(concat!(env!("OUT_DIR"), "/exp_tests.rs"));
+fn exit_status(status: ExitStatus, steps: u64) -> Result<(), anyhow::Error> {
+    match status {
+        ExitStatus::Terminated => Ok(()),
+        ExitStatus::BudgetExhausted => {
+            Err(anyhow::anyhow!("Stopped after {} steps without finishing", steps))
+        }
+        ExitStatus::Cancelled => Err(anyhow::anyhow!("Cancelled")),
+        ExitStatus::Paused | ExitStatus::Breakpoint | ExitStatus::Watchpoint => {
+            unreachable!("Stopped without a budget or watches")
+        }
+    }
 }
 
-use clap::{Arg, App};
+// Read debugger commands from standard input until the user quits. The program being debugged
+// shares standard input and output with the debugger.
+fn run_debugger<C: Cell>(builder: Builder, load_state: Option<&str>) -> Result<(), anyhow::Error> {
+    let mut debugger = Debugger::new(build::<_, C>(builder, load_state)?);
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
 
-mod space;
-mod jit;
+    loop {
+        write!(stdout, "(funjit) ")?;
+        stdout.flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 || !debugger.command(&line, &mut stdout)? {
+            return Ok(());
+        }
+    }
+}
+
+// Animate the program on standard output, which it shares with the program's own output pane.
+fn run_visualizer<C: Cell>(
+    builder: Builder,
+    load_state: Option<&str>,
+    speed: f64,
+) -> Result<(), anyhow::Error> {
+    let delay = std::time::Duration::try_from_secs_f64(1.0 / speed)?;
+    let mut visualizer = Visualizer::new(build::<_, C>(builder.io(PaneIO::new()), load_state)?);
+    let status = visualizer.run(delay, &mut io::stdout())?;
+    exit_status(status, visualizer.jit.steps)
+}
+
+// The arguments for running a program, which are shared by `funjit` and `funjit debug`.
+fn program_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("INPUT")
+            .required(true)
+            .index(1),
+        Arg::with_name("cells")
+            .long("cells")
+            .help("The width of funge space cells, and of the stack unless they're 8-bit")
+            .takes_value(true)
+            .possible_values(&["8", "32", "64", "big"])
+            .default_value("64"),
+        Arg::with_name("dialect")
+            .long("dialect")
+            .help("The Befunge standard to follow")
+            .takes_value(true)
+            .possible_values(&["93", "98"])
+            .default_value("93"),
+        Arg::with_name("division-by-zero")
+            .long("division-by-zero")
+            .help("What to do when dividing by zero, instead of following the dialect")
+            .takes_value(true)
+            .possible_values(&["ask", "zero", "trap"]),
+        Arg::with_name("overflow")
+            .long("overflow")
+            .help("What to do when arithmetic overflows the cell width")
+            .takes_value(true)
+            .possible_values(&["wrap", "saturate", "trap"])
+            .default_value("wrap"),
+        Arg::with_name("seed")
+            .long("seed")
+            .help("Seed the directions taken by `?`, to make runs reproducible")
+            .takes_value(true),
+        Arg::with_name("directions")
+            .long("directions")
+            .help("Directions for `?` to take in turn, written as the arrows ^>v<")
+            .takes_value(true)
+            .conflicts_with("seed"),
+        Arg::with_name("unbuffered")
+            .long("unbuffered")
+            .help("Flush output after every character or number, for interactive programs"),
+        Arg::with_name("max-steps")
+            .long("max-steps")
+            .help("Stop after this many steps, counting every cell the program moves through")
+            .takes_value(true),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .help("Stop after this many seconds")
+            .takes_value(true),
+        Arg::with_name("max-stack")
+            .long("max-stack")
+            .help("Fail if the stack grows deeper than this")
+            .takes_value(true),
+        Arg::with_name("max-code-size")
+            .long("max-code-size")
+            .help("Fail if more than this many bytes of machine code are compiled at once")
+            .takes_value(true),
+        Arg::with_name("interpret")
+            .long("interpret")
+            .help("Interpret the program a step at a time instead of compiling it"),
+        Arg::with_name("compile-threshold")
+            .long("compile-threshold")
+            .help("How many times a block is interpreted before it's compiled")
+            .takes_value(true)
+            .default_value("10"),
+        Arg::with_name("trace")
+            .long("trace")
+            .help("Record each step the program takes in FILE, as JSON Lines")
+            .value_name("FILE")
+            .takes_value(true),
+        Arg::with_name("trace-level")
+            .long("trace-level")
+            .help("Whether --trace records every step, or every pass through a compiled block")
+            .takes_value(true)
+            .possible_values(&["instruction", "block"])
+            .default_value("instruction"),
+        Arg::with_name("dump-blocks")
+            .long("dump-blocks")
+            .help("Describe each block on standard error as it's compiled, with its disassembly"),
+        Arg::with_name("perf-map")
+            .long("perf-map")
+            .help("Name compiled blocks for `perf` in /tmp/perf-PID.map"),
+        Arg::with_name("jitdump")
+            .long("jitdump")
+            .help("Also write compiled blocks to a jitdump for `perf inject --jit`")
+            .requires("perf-map"),
+        Arg::with_name("gdb")
+            .long("gdb")
+            .help("Describe compiled blocks to GDB, so that it can show them in backtraces"),
+        Arg::with_name("load-state")
+            .long("load-state")
+            .help("Carry on from the state saved in FILE, counting its steps towards --max-steps")
+            .value_name("FILE")
+            .takes_value(true),
+        Arg::with_name("stats")
+            .long("stats")
+            .help("Report how the program was run on standard error once it finishes"),
+    ]
+}
 
 fn main() -> Result<(), anyhow::Error> {
-    let matches = App::new("funjit")
+    let app_matches = App::new("funjit")
         .version("1.0")
-        .arg(Arg::with_name("INPUT")
-             .required(true)
-             .index(1))
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&program_args())
+        .arg(Arg::with_name("visualize")
+             .long("visualize")
+             .help("Animate the program running over the playfield, in the terminal"))
+        .arg(Arg::with_name("speed")
+             .long("speed")
+             .help("How many steps a second to animate with --visualize")
+             .takes_value(true)
+             .default_value("20"))
+        .arg(Arg::with_name("save-state")
+             .long("save-state")
+             .help("Save the state of a program that runs out of steps or time to FILE")
+             .value_name("FILE")
+             .takes_value(true))
+        .arg(Arg::with_name("profile")
+             .long("profile")
+             .help("Draw a heatmap of how often each cell ran on standard error once it finishes"))
+        .arg(Arg::with_name("profile-report")
+             .long("profile-report")
+             .help("Write how often each cell and trace ran to FILE")
+             .value_name("FILE")
+             .takes_value(true))
+        .arg(Arg::with_name("profile-format")
+             .long("profile-format")
+             .help("The format of --profile-report")
+             .takes_value(true)
+             .possible_values(&["csv", "json"])
+             .default_value("csv"))
+        .subcommand(SubCommand::with_name("debug")
+                    .about("Step through a program interactively")
+                    .args(&program_args()))
         .get_matches();
 
+    let (matches, debug) = match app_matches.subcommand_matches("debug") {
+        Some(matches) => (matches, true),
+        None => (&app_matches, false),
+    };
+
     let file = matches.value_of("INPUT").unwrap();
 
+    let options = jit::Options {
+        dialect: matches.value_of("dialect").unwrap().parse().map_err(anyhow::Error::msg)?,
+        division_by_zero: matches
+            .value_of("division-by-zero")
+            .map(str::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)?,
+        overflow: matches.value_of("overflow").unwrap().parse().map_err(anyhow::Error::msg)?,
+        seed: matches.value_of("seed").map(str::parse).transpose()?,
+        max_steps: matches.value_of("max-steps").map(str::parse).transpose()?,
+        timeout: matches
+            .value_of("timeout")
+            .map(str::parse)
+            .transpose()?
+            .map(std::time::Duration::try_from_secs_f64)
+            .transpose()?,
+        max_stack: matches.value_of("max-stack").map(str::parse).transpose()?,
+        max_code_size: matches.value_of("max-code-size").map(str::parse).transpose()?,
+        interpret: matches.is_present("interpret"),
+        profile: matches.is_present("profile") || matches.is_present("profile-report"),
+        compile_threshold: matches.value_of("compile-threshold").unwrap().parse()?,
+    };
+
+    let io = if matches.is_present("unbuffered") {
+        jit::StdIO::unbuffered()
+    } else {
+        jit::StdIO::new()
+    };
+
     let prog = std::fs::read_to_string(file)?;
-    let mut jit = jit::Jit::new(space::Funge93::from_string(&prog), jit::StdIO::new());
+    let mut builder = Builder::new(prog).io(io).options(options);
 
-    jit.run();
+    if let Some(directions) = matches.value_of("directions") {
+        let directions: random::Scripted = directions.parse().map_err(anyhow::Error::msg)?;
+        builder = builder.directions(Box::new(directions));
+    }
 
-    Ok(())
+    if let Some(path) = matches.value_of("trace") {
+        let level = matches.value_of("trace-level").unwrap().parse().map_err(anyhow::Error::msg)?;
+        let file = io::BufWriter::new(std::fs::File::create(path)?);
+        builder = builder.trace(Tracer::new(file, level));
+    }
+
+    if matches.is_present("dump-blocks") {
+        builder = builder.dump_blocks(BlockDumper::new(io::stderr()));
+    }
+
+    if matches.is_present("perf-map") {
+        builder = builder.perf(Perf::new(matches.is_present("jitdump"))?);
+    }
+
+    if matches.is_present("gdb") {
+        builder = builder.debug_info(DebugInfo::new(std::fs::canonicalize(file)?));
+    }
+
+    let load_state = matches.value_of("load-state");
+    let mode = if debug {
+        Mode::Debug
+    } else if matches.is_present("visualize") {
+        let speed: f64 = matches.value_of("speed").unwrap().parse()?;
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(anyhow::anyhow!("The speed must be more than zero steps a second"));
+        }
+        Mode::Visualize { speed }
+    } else {
+        let heatmap = matches.is_present("profile");
+        let report = matches.value_of("profile-report").map(String::from);
+        let format = matches.value_of("profile-format").unwrap();
+        let format = format.parse().map_err(anyhow::Error::msg)?;
+        let profile = (heatmap || report.is_some()).then_some(Profiling {
+            heatmap,
+            report,
+            format,
+        });
+        Mode::Run {
+            stats: matches.is_present("stats"),
+            save_state: matches.value_of("save-state").map(String::from),
+            profile,
+        }
+    };
+
+    match matches.value_of("cells").unwrap() {
+        "8" => run_in::<cell::Char>(builder, mode, load_state),
+        "32" => run_in::<i32>(builder, mode, load_state),
+        "big" => run_in::<num_bigint::BigInt>(builder, mode, load_state),
+        _ => run_in::<i64>(builder, mode, load_state),
+    }
 }
@@ -0,0 +1,173 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use super::cell::Cell;
+use super::jit::{Block, CompiledBlock, IO};
+
+/// Where `perf` looks for the names of this process's JIT-compiled code.
+pub fn map_path() -> PathBuf {
+    PathBuf::from(format!("/tmp/perf-{}.map", std::process::id()))
+}
+
+/// Where the jitdump for this process is written. `perf inject --jit` finds it through the
+/// mapping `JitDump` makes of it.
+pub fn jitdump_path() -> PathBuf {
+    std::env::temp_dir().join(format!("jit-{}.dump", std::process::id()))
+}
+
+/// Tells Linux `perf` about each block as it's compiled, naming it by where it starts in funge
+/// space, so that `perf report` attributes time to Befunge code rather than anonymous addresses.
+///
+/// The perf map is enough for `perf report` on its own. A jitdump also holds a copy of the machine
+/// code, which `perf inject --jit` uses to annotate it, and survives blocks being freed and their
+/// memory reused. The program has to be recorded with `perf record -k mono` for that.
+pub struct Perf {
+    map: Option<File>,
+    jitdump: Option<JitDump>,
+}
+
+impl Perf {
+    /// Start writing the perf map to `map_path`, and the jitdump to `jitdump_path` if `jitdump` is
+    /// set.
+    pub fn new(jitdump: bool) -> io::Result<Self> {
+        Ok(Perf {
+            map: Some(File::create(map_path())?),
+            jitdump: if jitdump { Some(JitDump::new()?) } else { None },
+        })
+    }
+
+    /// Record a block that's just been compiled.
+    pub fn load<I: IO, C: Cell>(
+        &mut self,
+        block: &Block,
+        compiled: &CompiledBlock<I, C>,
+    ) -> io::Result<()> {
+        let (pos, delta) = block.entry();
+        let name =
+            format!("befunge block at ({}, {}) moving ({}, {})", pos.x, pos.y, delta.x, delta.y);
+        let code = compiled.machine_code();
+
+        // each line is written at once, so that the map is complete if the program crashes
+        if let Some(map) = &mut self.map {
+            let line = format!("{:x} {:x} {}\n", compiled.addr(), code.len(), name);
+            map.write_all(line.as_bytes())?;
+        }
+        if let Some(jitdump) = &mut self.jitdump {
+            jitdump.load(&name, compiled.addr() as u64, code)?;
+        }
+        Ok(())
+    }
+}
+
+/// The jitdump format, described in `tools/perf/Documentation/jitdump-specification.txt` in the
+/// Linux source.
+struct JitDump {
+    file: File,
+    // the start of the file is mapped as executable, which is how `perf record` notices it. The
+    // address is kept as a number, so that the `Jit` can still be sent between threads
+    marker: usize,
+    // an index for each block, as perf needs one for every code load
+    loads: u64,
+}
+
+impl JitDump {
+    const MAGIC: u32 = 0x4A69_5444;
+    const VERSION: u32 = 1;
+    const HEADER_SIZE: u32 = 40;
+    const EM_X86_64: u32 = 62;
+    const JIT_CODE_LOAD: u32 = 0;
+    const JIT_CODE_CLOSE: u32 = 3;
+
+    fn new() -> io::Result<Self> {
+        // the file has to be readable to be mapped
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(jitdump_path())?;
+
+        let mut header = Vec::with_capacity(Self::HEADER_SIZE as usize);
+        header.extend(Self::MAGIC.to_ne_bytes());
+        header.extend(Self::VERSION.to_ne_bytes());
+        header.extend(Self::HEADER_SIZE.to_ne_bytes());
+        header.extend(Self::EM_X86_64.to_ne_bytes());
+        header.extend(0u32.to_ne_bytes());
+        header.extend(std::process::id().to_ne_bytes());
+        header.extend(timestamp().to_ne_bytes());
+        header.extend(0u64.to_ne_bytes());
+        file.write_all(&header)?;
+
+        let marker = unsafe {
+            use std::os::unix::io::AsRawFd;
+            let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            libc::mmap(
+                std::ptr::null_mut(),
+                page_size,
+                libc::PROT_READ | libc::PROT_EXEC,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if marker == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(JitDump {
+            file,
+            marker: marker as usize,
+            loads: 0,
+        })
+    }
+
+    fn load(&mut self, name: &str, addr: u64, code: &[u8]) -> io::Result<()> {
+        let size = 16 + 40 + name.len() + 1 + code.len();
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+
+        let mut record = Vec::with_capacity(size);
+        record.extend(Self::JIT_CODE_LOAD.to_ne_bytes());
+        record.extend((size as u32).to_ne_bytes());
+        record.extend(timestamp().to_ne_bytes());
+        record.extend(std::process::id().to_ne_bytes());
+        record.extend(tid.to_ne_bytes());
+        record.extend(addr.to_ne_bytes());
+        record.extend(addr.to_ne_bytes());
+        record.extend((code.len() as u64).to_ne_bytes());
+        record.extend(self.loads.to_ne_bytes());
+        record.extend(name.as_bytes());
+        record.push(0);
+        record.extend(code);
+        self.loads += 1;
+
+        self.file.write_all(&record)
+    }
+}
+
+impl Drop for JitDump {
+    fn drop(&mut self) {
+        let mut record = Vec::with_capacity(16);
+        record.extend(Self::JIT_CODE_CLOSE.to_ne_bytes());
+        record.extend(16u32.to_ne_bytes());
+        record.extend(timestamp().to_ne_bytes());
+        let _ = self.file.write_all(&record);
+
+        unsafe {
+            let page_size = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            libc::munmap(self.marker as *mut libc::c_void, page_size);
+        }
+    }
+}
+
+// The time on the clock that `perf record -k mono` uses, in nanoseconds.
+fn timestamp() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time);
+    }
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use super::cell::Cell;
+use super::jit::Origin;
+use super::space;
+
+/// Colors for the heatmap, from the coolest cells to the hottest, as 256-color palette indexes.
+const HEAT: [u8; 7] = [22, 28, 34, 142, 178, 208, 196];
+/// How many of the hottest traces are listed under the heatmap.
+const HOTTEST: usize = 10;
+
+const RESET: &str = "\x1b[0m";
+const UNEXECUTED: &str = "\x1b[2m";
+
+/// How `Profile::write_report` lays out a profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("Unknown profile report format: {}", s)),
+        }
+    }
+}
+
+/// Passes through a trace, which is a block that starts at a position and direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceCount {
+    /// The steps one pass through the trace takes.
+    pub steps: u64,
+    pub passes: u64,
+}
+
+/// How many times each cell was executed, and each trace passed through, whether the program
+/// was interpreted or compiled.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    // indexed by y * space::WIDTH + x
+    cells: Vec<u64>,
+    traces: HashMap<(space::Pos, space::Pos), TraceCount>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            cells: vec![0; space::WIDTH * space::HEIGHT],
+            traces: HashMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// The number of times the cell at `pos` was executed.
+    pub fn count(&self, pos: space::Pos) -> u64 {
+        self.cells[pos.y as usize * space::WIDTH + pos.x as usize]
+    }
+
+    /// Every trace that was passed through, with where it starts and the direction it's entered
+    /// in, hottest first.
+    pub fn traces(&self) -> Vec<(space::Pos, space::Pos, TraceCount)> {
+        let mut traces: Vec<_> =
+            self.traces.iter().map(|(&(pos, delta), &count)| (pos, delta, count)).collect();
+        traces.sort_by_key(|&(pos, delta, count)| {
+            (std::cmp::Reverse(count.steps * count.passes), pos.y, pos.x, delta.y, delta.x)
+        });
+        traces
+    }
+
+    pub(crate) fn step(&mut self, pos: space::Pos) {
+        self.cells[pos.y as usize * space::WIDTH + pos.x as usize] += 1;
+    }
+
+    pub(crate) fn pass(&mut self, pos: space::Pos, delta: space::Pos, steps: u64, passes: u64) {
+        let trace = self.traces.entry((pos, delta)).or_default();
+        trace.steps = steps;
+        trace.passes += passes;
+    }
+
+    /// Draw funge space with each cell colored by how often it was executed, using ANSI escape
+    /// codes, with the hottest traces listed underneath. Only the rows and columns up to the last
+    /// instruction or executed cell are drawn.
+    pub fn heatmap<C: Cell>(&self, cells: &space::Funge93<C>) -> String {
+        let max = self.cells.iter().copied().max().unwrap_or(0);
+        let used = |x: usize, y: usize| {
+            cells.instr(x, y) != b' ' || self.cells[y * space::WIDTH + x] > 0
+        };
+        let height = (0..space::HEIGHT).rev().find(|&y| (0..space::WIDTH).any(|x| used(x, y)));
+        let width = (0..space::WIDTH).rev().find(|&x| (0..space::HEIGHT).any(|y| used(x, y)));
+
+        let mut map = String::new();
+        if let (Some(height), Some(width)) = (height, width) {
+            for y in 0..=height {
+                for x in 0..=width {
+                    let instr = cells.instr(x, y);
+                    let c = if instr.is_ascii_graphic() { instr as char } else { ' ' };
+                    match self.cells[y * space::WIDTH + x] {
+                        0 => {
+                            let _ = write!(map, "{}{}{}", UNEXECUTED, c, RESET);
+                        }
+                        count => {
+                            let color = HEAT[heat(count, max)];
+                            let _ = write!(map, "\x1b[30;48;5;{}m{}{}", color, c, RESET);
+                        }
+                    }
+                }
+                map.push('\n');
+            }
+        }
+
+        map.push_str("\nexecutions: 1 ");
+        for color in &HEAT {
+            let _ = write!(map, "\x1b[48;5;{}m  {}", color, RESET);
+        }
+        let _ = writeln!(map, " {}", max);
+
+        let traces = self.traces();
+        if !traces.is_empty() {
+            map.push_str("\nhottest traces:\n");
+        }
+        for (pos, delta, count) in traces.into_iter().take(HOTTEST) {
+            let _ = writeln!(
+                map,
+                "  ({}, {}) moving ({}, {}): {} passes of {} steps",
+                pos.x, pos.y, delta.x, delta.y, count.passes, count.steps
+            );
+        }
+
+        map
+    }
+
+    /// Write the profile in the given format, with `write_csv` or `write_json`.
+    pub fn write_report<C: Cell>(
+        &self,
+        cells: &space::Funge93<C>,
+        format: ReportFormat,
+        out: impl Write,
+    ) -> io::Result<()> {
+        match format {
+            ReportFormat::Csv => self.write_csv(cells, out),
+            ReportFormat::Json => self.write_json(cells, out),
+        }
+    }
+
+    /// Write a row for every executed cell and trace as CSV. Cells have no delta, and traces no
+    /// instruction, with `count` giving executions and passes respectively.
+    pub fn write_csv<C: Cell>(
+        &self,
+        cells: &space::Funge93<C>,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        writeln!(out, "kind,x,y,dx,dy,instr,steps,count")?;
+        for (pos, count) in self.executed() {
+            let instr = cells.instr(pos.x as usize, pos.y as usize);
+            let instr = match instr {
+                b'"' => String::from("\"\"\"\""),
+                b',' => String::from("\",\""),
+                b' '..=b'~' => (instr as char).to_string(),
+                _ => format!("\\x{:02x}", instr),
+            };
+            writeln!(out, "cell,{},{},,,{},1,{}", pos.x, pos.y, instr, count)?;
+        }
+        for (pos, delta, count) in self.traces() {
+            writeln!(
+                out,
+                "trace,{},{},{},{},,{},{}",
+                pos.x, pos.y, delta.x, delta.y, count.steps, count.passes
+            )?;
+        }
+        out.flush()
+    }
+
+    /// Write the profile as a JSON object:
+    ///
+    /// ```text
+    /// {"cells":[{"pos":[0,0],"instr":"1","count":1},...],
+    ///  "traces":[{"pos":[0,0],"delta":[1,0],"steps":5,"passes":1},...]}
+    /// ```
+    pub fn write_json<C: Cell>(
+        &self,
+        cells: &space::Funge93<C>,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        #[derive(Serialize)]
+        struct CellReport {
+            pos: (isize, isize),
+            instr: String,
+            count: u64,
+        }
+        #[derive(Serialize)]
+        struct TraceReport {
+            pos: (isize, isize),
+            delta: (isize, isize),
+            steps: u64,
+            passes: u64,
+        }
+        #[derive(Serialize)]
+        struct Report {
+            cells: Vec<CellReport>,
+            traces: Vec<TraceReport>,
+        }
+
+        let report = Report {
+            cells: self
+                .executed()
+                .map(|(pos, count)| CellReport {
+                    pos: (pos.x, pos.y),
+                    instr: (cells.instr(pos.x as usize, pos.y as usize) as char).to_string(),
+                    count,
+                })
+                .collect(),
+            traces: self
+                .traces()
+                .into_iter()
+                .map(|(pos, delta, count)| TraceReport {
+                    pos: (pos.x, pos.y),
+                    delta: (delta.x, delta.y),
+                    steps: count.steps,
+                    passes: count.passes,
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut out, &report)?;
+        writeln!(out)?;
+        out.flush()
+    }
+
+    // The cells that were executed, a row at a time.
+    fn executed(&self) -> impl Iterator<Item = (space::Pos, u64)> + '_ {
+        self.cells.iter().enumerate().filter(|(_, &count)| count > 0).map(|(i, &count)| {
+            let pos = space::Pos::new((i % space::WIDTH) as isize, (i / space::WIDTH) as isize);
+            (pos, count)
+        })
+    }
+}
+
+// Which of `HEAT` a cell executed `count` times is drawn in, on a log scale.
+fn heat(count: u64, max: u64) -> usize {
+    if max <= 1 {
+        return HEAT.len() - 1;
+    }
+    let scale = (count as f64).ln() / (max as f64).ln();
+    ((scale * (HEAT.len() - 1) as f64).round() as usize).min(HEAT.len() - 1)
+}
+
+/// The counters a compiled block keeps as it runs, which are added to the `Profile` when it's
+/// read. The first counts passes through the block, and the rest count passes that left the
+/// block early, by how many of its cells were executed.
+pub struct BlockCounter {
+    cells: Vec<space::Pos>,
+    counts: Box<[u64]>,
+}
+
+impl BlockCounter {
+    pub fn new(cells: &[Origin]) -> Self {
+        BlockCounter {
+            cells: cells.iter().map(|cell| cell.pos).collect(),
+            counts: vec![0; cells.len() + 2].into_boxed_slice(),
+        }
+    }
+
+    /// The address of the counters, which compiled code adds to.
+    pub fn addr(&self) -> usize {
+        self.counts.as_ptr() as usize
+    }
+
+    /// Add the counts so far to `profile` for the block at `pos` and `delta`, and start again.
+    pub fn collect(&mut self, profile: &mut Profile, pos: space::Pos, delta: space::Pos) {
+        let passes = self.counts[0];
+        if passes == 0 {
+            return;
+        }
+        profile.pass(pos, delta, self.cells.len() as u64, passes);
+
+        // a pass that left after executing `i` cells doesn't count for cell `i` onwards
+        let mut left = 0;
+        for (i, &pos) in self.cells.iter().enumerate() {
+            left += self.counts[i + 1];
+            profile.cells[pos.y as usize * space::WIDTH + pos.x as usize] += passes - left;
+        }
+        self.counts.iter_mut().for_each(|count| *count = 0);
+    }
+}
@@ -0,0 +1,128 @@
+use super::space;
+
+/// The directions `?` chooses between, in the order that compiled code numbers them.
+pub const DIRECTIONS: [space::Pos; 4] = [
+    space::Pos { x: 0, y: -1 },
+    space::Pos { x: 1, y: 0 },
+    space::Pos { x: 0, y: 1 },
+    space::Pos { x: -1, y: 0 },
+];
+
+/// The index of a direction in `DIRECTIONS`.
+pub fn direction_index(delta: space::Pos) -> usize {
+    DIRECTIONS
+        .iter()
+        .position(|dir| *dir == delta)
+        .expect("Not a cardinal direction")
+}
+
+/// Where `?` gets its directions from.
+pub trait Directions {
+    fn next_direction(&mut self) -> space::Pos;
+}
+
+/// The default source of directions, a xorshift generator. Compiled code steps it inline, so the
+/// algorithm in `next_u64` is mirrored in `Block::compile`.
+#[derive(Clone, Debug)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub const STATE_OFFSET: usize = std::mem::offset_of!(XorShift, state);
+
+    /// A generator that produces the same directions every time it's given the same seed.
+    pub fn new(seed: u64) -> Self {
+        // run the seed through splitmix64, so that similar seeds give unrelated sequences and the
+        // state is never zero, which xorshift can't leave
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        XorShift {
+            state: if z == 0 { 1 } else { z },
+        }
+    }
+
+    /// A generator that carries on from the `state` of another.
+    pub fn from_state(state: u64) -> Self {
+        XorShift {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
+
+impl Directions for XorShift {
+    fn next_direction(&mut self) -> space::Pos {
+        // the high bits of xorshift are the most random
+        DIRECTIONS[(self.next_u64() >> 62) as usize]
+    }
+}
+
+/// Directions given up front, which are repeated once they run out.
+#[derive(Clone, Debug)]
+pub struct Scripted {
+    directions: Vec<space::Pos>,
+    next: usize,
+}
+
+impl Scripted {
+    pub fn new(directions: Vec<space::Pos>) -> Self {
+        assert!(!directions.is_empty(), "No directions to follow");
+        Scripted {
+            directions,
+            next: 0,
+        }
+    }
+}
+
+impl std::str::FromStr for Scripted {
+    type Err = String;
+
+    /// Parse directions written as the arrows `^>v<`, ignoring whitespace.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let directions = s
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                '^' => Ok(space::Pos::north()),
+                '>' => Ok(space::Pos::east()),
+                'v' => Ok(space::Pos::south()),
+                '<' => Ok(space::Pos::west()),
+                _ => Err(format!("Unknown direction: {}", c)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if directions.is_empty() {
+            return Err(String::from("No directions given"));
+        }
+
+        Ok(Self::new(directions))
+    }
+}
+
+impl Directions for Scripted {
+    fn next_direction(&mut self) -> space::Pos {
+        let dir = self.directions[self.next];
+        self.next = (self.next + 1) % self.directions.len();
+        dir
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{self, Read};
+
+/// Reads characters and numbers from an input stream. Characters can be put back, so that `~`
+/// after `&` sees whatever followed the number.
+pub struct Reader<R: Read> {
+    input: R,
+    pending: Vec<u8>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(input: R) -> Self {
+        Reader {
+            input,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Read a single byte, or `None` at the end of the input.
+    pub fn read_char(&mut self) -> io::Result<Option<u8>> {
+        if let Some(c) = self.pending.pop() {
+            return Ok(Some(c));
+        }
+
+        let mut buf = [0; 1];
+        loop {
+            match self.input.read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(buf[0])),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Put a byte back, so that it's returned by the next read.
+    pub fn unread(&mut self, c: u8) {
+        self.pending.push(c)
+    }
+
+    /// The bytes that have been put back, with the next to be read last.
+    pub fn pending(&self) -> &[u8] {
+        &self.pending
+    }
+
+    /// Skip everything up to the next decimal number and read it, leaving the character that
+    /// follows it in the input. A `-` immediately before the digits makes the number negative,
+    /// and numbers too large for an `isize` saturate. Returns `None` if the input ends before a
+    /// number is found.
+    pub fn read_number(&mut self) -> io::Result<Option<isize>> {
+        let mut prev = None;
+        let first = loop {
+            match self.read_char()? {
+                Some(c) if c.is_ascii_digit() => break c,
+                Some(c) => prev = Some(c),
+                None => return Ok(None),
+            }
+        };
+
+        let negative = prev == Some(b'-');
+        let mut num = digit(first, negative);
+        loop {
+            match self.read_char()? {
+                Some(c) if c.is_ascii_digit() => {
+                    num = num.saturating_mul(10).saturating_add(digit(c, negative));
+                }
+                Some(c) => {
+                    self.unread(c);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        Ok(Some(num))
+    }
+}
+
+// Accumulating negative numbers digit by digit lets them reach `isize::MIN`.
+fn digit(c: u8, negative: bool) -> isize {
+    let val = (c - b'0') as isize;
+    if negative {
+        -val
+    } else {
+        val
+    }
+}
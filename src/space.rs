@@ -1,3 +1,8 @@
+use super::cell::Cell;
+
+pub const WIDTH: usize = 80;
+pub const HEIGHT: usize = 24;
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
 pub struct Pos {
     pub x: isize,
@@ -28,8 +33,8 @@ impl Pos {
     pub fn move_by(&mut self, other: &Self) {
         self.x += other.x;
         self.y += other.y;
-        self.x = self.x.rem_euclid(Funge93::WIDTH as isize);
-        self.y = self.y.rem_euclid(Funge93::HEIGHT as isize);
+        self.x = self.x.rem_euclid(WIDTH as isize);
+        self.y = self.y.rem_euclid(HEIGHT as isize);
     }
 }
 
@@ -38,47 +43,56 @@ fn test_pos_move() {
     {
         let mut pos = Pos::new(0, 0);
         pos.move_by(&Pos::new(-1, 0));
-        assert_eq!(Funge93::WIDTH as isize - 1, pos.x);
+        assert_eq!(WIDTH as isize - 1, pos.x);
     }
 
     {
         let mut pos = Pos::new(0, 0);
         pos.move_by(&Pos::new(0, -1));
-        assert_eq!(Funge93::HEIGHT as isize - 1, pos.y);
+        assert_eq!(HEIGHT as isize - 1, pos.y);
     }
 }
 
-pub struct Funge93 {
-    rows: [[u8; Self::WIDTH]; Self::HEIGHT],
+#[derive(Clone, Debug)]
+pub struct Funge93<C: Cell> {
+    cells: Vec<C>,
 }
 
-impl Funge93 {
-    pub const WIDTH: usize = 80;
-    pub const HEIGHT: usize = 24;
-
+impl<C: Cell> Funge93<C> {
     pub fn new() -> Self {
         Funge93 {
-            rows: [[b' '; Self::WIDTH]; Self::HEIGHT],
+            cells: vec![C::from_isize(b' ' as isize); WIDTH * HEIGHT],
         }
     }
 
     pub fn from_string(prog: &str) -> Self {
         let mut space = Self::new();
 
-        for (y, line) in prog.lines().enumerate().take(Self::HEIGHT) {
-            for (x, c) in line.bytes().enumerate().take(Self::WIDTH) {
-                space.set(x, y, c)
+        for (y, line) in prog.lines().enumerate().take(HEIGHT) {
+            for (x, c) in line.bytes().enumerate().take(WIDTH) {
+                space.set(x, y, C::from_isize(c as isize))
             }
         }
 
         space
     }
 
-    pub fn get(&self, x: usize, y: usize) -> u8 {
-        self.rows[y][x]
+    pub fn get(&self, x: usize, y: usize) -> &C {
+        &self.cells[y * WIDTH + x]
+    }
+
+    /// The instruction stored at the given position.
+    pub fn instr(&self, x: usize, y: usize) -> u8 {
+        self.get(x, y).to_instr()
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, val: C) {
+        self.cells[y * WIDTH + x] = val.stored()
     }
+}
 
-    pub fn set(&mut self, x: usize, y: usize, val: u8) {
-        self.rows[y][x] = val
+impl<C: Cell> Default for Funge93<C> {
+    fn default() -> Self {
+        Self::new()
     }
 }
@@ -0,0 +1,166 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::cell::Cell;
+use super::history::{Snapshot, TapeCursor};
+use super::jit::{Jit, IO};
+use super::random;
+use super::space;
+
+/// The version of the format written by `save`. Files from other versions aren't loaded.
+pub const VERSION: u32 = 1;
+
+/// Why a saved state couldn't be written or read.
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    /// The file was written in a version of the format this build doesn't know.
+    Version(u32),
+    /// The file holds cells of a different width to the program loading it.
+    Cells { expected: String, found: String },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Io(err) => write!(f, "Failed to access the saved state: {}", err),
+            StateError::Format(err) => write!(f, "Malformed saved state: {}", err),
+            StateError::Version(version) => write!(
+                f,
+                "Saved state is version {}, but only version {} can be loaded",
+                version, VERSION
+            ),
+            StateError::Cells { expected, found } => write!(
+                f,
+                "Saved state has {} cells, but the program uses {} cells",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(err: io::Error) -> Self {
+        StateError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for StateError {
+    fn from(err: serde_json::Error) -> Self {
+        StateError::Format(err)
+    }
+}
+
+/// Everything about a running program that's needed to carry on from where it left off, as it's
+/// written to a file. Compiled code isn't saved, and is compiled again as it's needed.
+///
+/// The directions for `?` are only saved when they come from `Jit::rng`, rather than
+/// `Jit::directions`.
+#[derive(Serialize, Deserialize)]
+struct SavedState<C> {
+    version: u32,
+    cells: String,
+    steps: u64,
+    /// Funge space, a row at a time.
+    space: Vec<Vec<C>>,
+    stack: Vec<C>,
+    pc: (isize, isize),
+    delta: (isize, isize),
+    string_mode: bool,
+    rng: u64,
+    /// Input that was read ahead but not used.
+    pending_input: Vec<u8>,
+}
+
+/// Write the state of `jit` to `out`.
+pub fn save<I: IO, C: Cell>(jit: &Jit<I, C>, out: impl Write) -> Result<(), StateError> {
+    let snapshot = jit.snapshot();
+    let space = (0..space::HEIGHT)
+        .map(|y| (0..space::WIDTH).map(|x| snapshot.cells.get(x, y).clone()).collect())
+        .collect();
+
+    let state = SavedState {
+        version: VERSION,
+        cells: String::from(C::NAME),
+        steps: snapshot.steps,
+        space,
+        stack: snapshot.stack,
+        pc: (snapshot.pc.x, snapshot.pc.y),
+        delta: (snapshot.delta.x, snapshot.delta.y),
+        string_mode: snapshot.string_mode,
+        rng: jit.rng.state(),
+        pending_input: jit.io.pending_input(),
+    };
+
+    let mut out = io::BufWriter::new(out);
+    serde_json::to_writer(&mut out, &state)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Replace the state of `jit` with the state read from `input`, which `save` wrote. The program's
+/// options and IO are left as they are.
+pub fn load<I: IO, C: Cell>(jit: &mut Jit<I, C>, input: impl Read) -> Result<(), StateError> {
+    // the version and cells are checked before anything else, in case the rest has changed shape
+    // or holds values that don't fit
+    #[derive(Deserialize)]
+    struct Header {
+        version: u32,
+        #[serde(default)]
+        cells: String,
+    }
+
+    let mut json = String::new();
+    io::BufReader::new(input).read_to_string(&mut json)?;
+    let header: Header = serde_json::from_str(&json)?;
+    if header.version != VERSION {
+        return Err(StateError::Version(header.version));
+    }
+    if header.cells != C::NAME {
+        return Err(StateError::Cells {
+            expected: String::from(C::NAME),
+            found: header.cells,
+        });
+    }
+
+    let state: SavedState<C> = serde_json::from_str(&json)?;
+
+    let in_bounds = (0..space::WIDTH as isize).contains(&state.pc.0)
+        && (0..space::HEIGHT as isize).contains(&state.pc.1);
+    if !in_bounds {
+        let err = format!("pc ({}, {}) is outside the playfield", state.pc.0, state.pc.1);
+        return Err(StateError::Format(serde::de::Error::custom(err)));
+    }
+    let delta = space::Pos::new(state.delta.0, state.delta.1);
+    if !random::DIRECTIONS.contains(&delta) {
+        let err = format!("delta ({}, {}) isn't one of the four directions", delta.x, delta.y);
+        return Err(StateError::Format(serde::de::Error::custom(err)));
+    }
+
+    let mut cells = space::Funge93::new();
+    for (y, row) in state.space.into_iter().enumerate().take(space::HEIGHT) {
+        for (x, val) in row.into_iter().enumerate().take(space::WIDTH) {
+            cells.set(x, y, val);
+        }
+    }
+
+    jit.restore(&Snapshot {
+        steps: state.steps,
+        cells,
+        stack: state.stack,
+        pc: space::Pos::new(state.pc.0, state.pc.1),
+        delta,
+        string_mode: state.string_mode,
+        tape: TapeCursor::default(),
+    });
+    jit.rng = random::XorShift::from_state(state.rng);
+    jit.io.unread_input(&state.pending_input);
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use super::space;
+
+/// How often a `Tracer` records an event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceLevel {
+    /// Before every step, including the spaces and arrows that compiled code skips over.
+    Instruction,
+    /// Before every pass through a block, or an instruction that's interpreted on its own.
+    Block,
+}
+
+impl std::str::FromStr for TraceLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instruction" => Ok(TraceLevel::Instruction),
+            "block" => Ok(TraceLevel::Block),
+            _ => Err(format!("Unknown trace level: {}", s)),
+        }
+    }
+}
+
+/// Writes what a program does as JSON Lines, one object per event:
+///
+/// ```text
+/// {"step":3,"pos":[3,0],"delta":[1,0],"instr":"+","top":2}
+/// ```
+///
+/// `step` counts the steps taken before the event, and `top` is the value on top of the stack,
+/// or `null` if it's empty. Block events also give the `steps` the block takes.
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    pub level: TraceLevel,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + Send + 'static, level: TraceLevel) -> Self {
+        Tracer {
+            out: Box::new(out),
+            level,
+        }
+    }
+
+    pub fn event(
+        &mut self,
+        step: u64,
+        pos: space::Pos,
+        delta: space::Pos,
+        instr: u8,
+        top: Option<&dyn fmt::Display>,
+        steps: Option<u64>,
+    ) -> io::Result<()> {
+        write!(
+            self.out,
+            "{{\"step\":{},\"pos\":[{},{}],\"delta\":[{},{}],\"instr\":\"",
+            step, pos.x, pos.y, delta.x, delta.y
+        )?;
+        match instr {
+            b'"' | b'\\' => write!(self.out, "\\{}", instr as char)?,
+            b' '..=b'~' => write!(self.out, "{}", instr as char)?,
+            _ => write!(self.out, "\\u{:04x}", instr)?,
+        }
+        match top {
+            Some(top) => write!(self.out, "\",\"top\":{}", top)?,
+            None => write!(self.out, "\",\"top\":null")?,
+        }
+        match steps {
+            Some(steps) => writeln!(self.out, ",\"steps\":{}}}", steps),
+            None => writeln!(self.out, "}}"),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
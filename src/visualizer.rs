@@ -0,0 +1,150 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::Duration;
+
+use super::cell::Cell;
+use super::error::FunjitError;
+use super::jit::{Budget, ExitStatus, Jit, IO};
+use super::reader;
+use super::space;
+
+/// How many of the most recently executed cells are highlighted.
+const TRAIL: usize = 16;
+/// How many lines of output are shown under the playfield.
+const OUTPUT_LINES: usize = 5;
+
+const RESET: &str = "\x1b[0m";
+const IP: &str = "\x1b[7m";
+const RECENT: &str = "\x1b[33m";
+
+/// Reads from standard input like `StdIO`, but keeps output to be drawn in its own pane.
+pub struct PaneIO {
+    input: reader::Reader<io::Stdin>,
+    pub output: Vec<u8>,
+}
+
+impl PaneIO {
+    pub fn new() -> Self {
+        PaneIO {
+            input: reader::Reader::new(io::stdin()),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl Default for PaneIO {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IO for PaneIO {
+    fn input_char(&mut self) -> io::Result<Option<u8>> {
+        self.input.read_char()
+    }
+
+    fn input_number(&mut self) -> io::Result<Option<isize>> {
+        self.input.read_number()
+    }
+
+    fn output_char(&mut self, c: u8) -> io::Result<()> {
+        self.output.push(c);
+        Ok(())
+    }
+
+    fn output_number(&mut self, n: &dyn std::fmt::Display) -> io::Result<()> {
+        self.output.extend(n.to_string().bytes());
+        Ok(())
+    }
+
+    fn pending_input(&self) -> Vec<u8> {
+        self.input.pending().to_vec()
+    }
+
+    fn unread_input(&mut self, pending: &[u8]) {
+        for &c in pending {
+            self.input.unread(c);
+        }
+    }
+}
+
+/// Animates a program in the terminal, a step at a time.
+pub struct Visualizer<C: Cell> {
+    pub jit: Jit<PaneIO, C>,
+    recent: VecDeque<space::Pos>,
+}
+
+impl<C: Cell> Visualizer<C> {
+    pub fn new(jit: Jit<PaneIO, C>) -> Self {
+        Visualizer {
+            jit,
+            recent: VecDeque::with_capacity(TRAIL),
+        }
+    }
+
+    /// Run the program to completion, drawing a frame to `out` after every step and waiting
+    /// `delay` between them.
+    pub fn run(&mut self, delay: Duration, out: &mut dyn Write) -> Result<ExitStatus, FunjitError> {
+        // errors drawing frames don't stop the program
+        let _ = write!(out, "\x1b[2J");
+
+        loop {
+            let pc = self.jit.pc;
+            let status = self.jit.run_for(Budget::Steps(1));
+
+            if self.recent.len() == TRAIL {
+                self.recent.pop_back();
+            }
+            self.recent.push_front(pc);
+
+            let _ = write!(out, "\x1b[H{}", self.frame()).and_then(|()| out.flush());
+            match status? {
+                ExitStatus::Paused => std::thread::sleep(delay),
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Draw the playfield with the stack beside it and the output below, using ANSI escape codes
+    /// to highlight the IP and the cells it's just left.
+    pub fn frame(&self) -> String {
+        let pc = self.jit.pc;
+        let stack = &self.jit.stack;
+        let mut frame = String::new();
+
+        for y in 0..space::HEIGHT {
+            for x in 0..space::WIDTH {
+                let pos = space::Pos::new(x as isize, y as isize);
+                let instr = self.jit.cells.instr(x, y);
+                let c = if instr.is_ascii_graphic() { instr as char } else { ' ' };
+
+                if pos == pc {
+                    let _ = write!(frame, "{}{}{}", IP, c, RESET);
+                } else if self.recent.contains(&pos) {
+                    let _ = write!(frame, "{}{}{}", RECENT, c, RESET);
+                } else {
+                    frame.push(c);
+                }
+            }
+
+            // the top of the stack is drawn on the first row
+            frame.push_str(" \u{2502} ");
+            if let Some(val) = stack.len().checked_sub(y + 1).map(|i| &stack[i]) {
+                let _ = write!(frame, "{}", val);
+            }
+            frame.push_str("\x1b[K\n");
+        }
+
+        frame.push_str(&"\u{2500}".repeat(space::WIDTH));
+        frame.push('\n');
+
+        let output = String::from_utf8_lossy(&self.jit.io.output);
+        let lines: Vec<&str> = output.split('\n').collect();
+        for line in &lines[lines.len().saturating_sub(OUTPUT_LINES)..] {
+            let _ = writeln!(frame, "{}\x1b[K", line);
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,463 @@
+extern crate funjit;
+
+mod common;
+
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use common::{SharedBuffer, VecIO};
+use funjit::cell::Char;
+use funjit::dump::BlockDumper;
+use funjit::error::Limit;
+use funjit::jit::{Budget, Dialect, StdIO};
+use funjit::perf::{self, Perf};
+use funjit::profile::ReportFormat;
+use funjit::random::{Directions, Scripted};
+use funjit::space::{self, Pos};
+use funjit::trace::{TraceLevel, Tracer};
+use funjit::{Builder, CancelToken, ExitStatus, FunjitError, Jit};
+
+#[test]
+fn test_run() {
+    let result = Builder::new("\"olleH\",,,,,@").io(VecIO::new("")).run();
+    assert_eq!(ExitStatus::Terminated, result.result.unwrap());
+    assert_eq!("Hello", result.io.output());
+    assert!(result.stack.is_empty());
+    assert_eq!(13, result.steps);
+}
+
+#[test]
+fn test_input() {
+    let result = Builder::new("&&+.@").io(VecIO::new("19 23")).run();
+    result.result.unwrap();
+    assert_eq!("42", result.io.output());
+}
+
+#[test]
+fn test_stack() {
+    let result = Builder::new("123@").io(VecIO::new("")).run();
+    result.result.unwrap();
+    assert_eq!(vec![1, 2, 3], result.stack);
+}
+
+#[test]
+fn test_cells() {
+    // 8-bit cells only narrow values stored in funge space, and not those on the stack
+    let result = Builder::new("88*4*:*:.84*,01p01g.@").io(VecIO::new("")).cells::<Char>().run();
+    result.result.unwrap();
+    assert_eq!("65536 0", result.io.output());
+}
+
+#[test]
+fn test_dialect() {
+    let result = Builder::new("~.@").io(VecIO::new("")).run();
+    result.result.unwrap();
+    assert_eq!("-1", result.io.output());
+
+    // running out of input reflects in Befunge-98, around to the `@`
+    let result = Builder::new("~.@")
+        .io(VecIO::new(""))
+        .dialect(Dialect::Befunge98)
+        .run();
+    result.result.unwrap();
+    assert_eq!("", result.io.output());
+}
+
+#[test]
+fn test_max_steps() {
+    let result = Builder::new(">v\n^<").io(VecIO::new("")).max_steps(1000).run();
+    assert_eq!(ExitStatus::BudgetExhausted, result.result.unwrap());
+    assert_eq!(1000, result.steps);
+}
+
+#[test]
+fn test_max_stack() {
+    let result = Builder::new("1").io(VecIO::new("")).max_stack(10).run();
+    match result.result {
+        Err(FunjitError::LimitExceeded(Limit::StackDepth(10))) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+    assert_eq!(10, result.stack.len());
+}
+
+#[test]
+fn test_seed() {
+    let run = |seed| {
+        let result = Builder::new("v\n>?.@\n 1\n 2\n 3\n 4")
+            .io(VecIO::new(""))
+            .seed(seed)
+            .max_steps(1000)
+            .run();
+        result.io.output().to_string()
+    };
+    assert_eq!(run(7), run(7));
+}
+
+#[test]
+fn test_set_directions() {
+    // always goes east, counting the directions it's asked for
+    struct East(Arc<AtomicUsize>);
+
+    impl Directions for East {
+        fn next_direction(&mut self) -> Pos {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Pos::east()
+        }
+    }
+
+    // the `?` is compiled to step the generator inline before the directions are replaced, and
+    // asks for every direction after that, as it does when it's interpreted
+    let asked = |interpret| {
+        let mut jit = Builder::new(">?<").io(VecIO::new("")).seed(1).interpret(interpret).build();
+        assert_eq!(ExitStatus::Paused, jit.run_for(Budget::Steps(1000)).unwrap());
+        assert_eq!(interpret, jit.stats.blocks_compiled == 0);
+
+        let asked = Arc::new(AtomicUsize::new(0));
+        jit.set_directions(Box::new(East(asked.clone())));
+        assert_eq!(ExitStatus::Paused, jit.run_for(Budget::Steps(1000)).unwrap());
+        asked.load(Ordering::Relaxed)
+    };
+    assert!(asked(true) > 0);
+    assert_eq!(asked(true), asked(false));
+}
+
+#[test]
+fn test_cancelled() {
+    let token = CancelToken::new();
+    token.cancel();
+    let result = Builder::new(">v\n^<").io(VecIO::new("")).cancel_token(token).run();
+    assert_eq!(ExitStatus::Cancelled, result.result.unwrap());
+    assert_eq!(0, result.steps);
+}
+
+#[test]
+fn test_set_cancel_token() {
+    // the loop is compiled before the token is swapped, and has to stop for the new one while
+    // it's running
+    let max_steps = 2_000_000_000;
+    let mut jit = Builder::new(">v\n^<").io(VecIO::new("")).max_steps(max_steps).build();
+    assert_eq!(ExitStatus::Paused, jit.run_for(Budget::Steps(1001)).unwrap());
+    assert!(jit.stats.blocks_compiled > 0);
+
+    let token = CancelToken::new();
+    jit.set_cancel_token(token.clone());
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        token.cancel();
+    });
+    assert_eq!(ExitStatus::Cancelled, jit.run().unwrap());
+    // without the new token, the loop only stops when the step limit makes it check
+    assert!(jit.steps < max_steps / 2, "{} steps", jit.steps);
+    canceller.join().unwrap();
+}
+
+#[test]
+fn test_timeout_in_slices() {
+    // the timeout covers every slice together, rather than starting again for each one
+    let mut jit = Builder::new(">v\n^<")
+        .io(VecIO::new(""))
+        .timeout(Duration::from_millis(100))
+        .build();
+    let start = Instant::now();
+    loop {
+        match jit.run_for(Budget::Steps(1000)).unwrap() {
+            ExitStatus::Paused => assert!(start.elapsed().as_secs() < 5, "Program didn't time out"),
+            status => {
+                assert_eq!(ExitStatus::BudgetExhausted, status);
+                break;
+            }
+        }
+    }
+    assert!(start.elapsed() >= Duration::from_millis(100));
+}
+
+// Run a program to completion in slices of `budget`, returning its output, the steps it took and
+// the number of times it paused.
+fn run_in_slices(source: &str, input: &str, budget: Budget) -> (String, u64, usize) {
+    let mut jit = Builder::new(source).io(VecIO::new(input)).seed(1).build();
+    let mut pauses = 0;
+    while jit.run_for(budget).unwrap() == ExitStatus::Paused {
+        pauses += 1;
+        assert!(pauses < 100_000, "Program didn't finish");
+    }
+    (jit.io.output().to_string(), jit.steps, pauses)
+}
+
+#[test]
+fn test_run_for_steps() {
+    for name in &["hello.bf", "control_flow.bf", "put_wide.bf", "random.bf"] {
+        let source = common::fixture(name);
+        let full = Builder::new(source.as_str()).io(VecIO::new("")).seed(1).run();
+        full.result.unwrap();
+
+        for &n in &[1, 3, 64] {
+            let (output, steps, pauses) = run_in_slices(&source, "", Budget::Steps(n));
+            assert_eq!(full.io.output(), output, "{} in slices of {}", name, n);
+            assert_eq!(full.steps, steps, "{} in slices of {}", name, n);
+            assert_eq!(((steps - 1) / n) as usize, pauses, "{} in slices of {}", name, n);
+        }
+    }
+}
+
+#[test]
+fn test_run_for_traces() {
+    let source = common::fixture("control_flow.bf");
+    let full = Builder::new(source.as_str()).io(VecIO::new("")).run();
+
+    let (output, steps, pauses) = run_in_slices(&source, "", Budget::Traces(1));
+    assert_eq!(full.io.output(), output);
+    assert_eq!(full.steps, steps);
+    assert!(pauses > 1);
+}
+
+#[test]
+fn test_run_for_input() {
+    let (output, _, _) = run_in_slices("&&+.@", "19 23", Budget::Steps(1));
+    assert_eq!("42", output);
+}
+
+#[test]
+fn test_interleave() {
+    let mut a = Builder::new("\"a\">:,").io(VecIO::new("")).build();
+    let mut b = Builder::new("\"b\">:,").io(VecIO::new("")).build();
+    for _ in 0..3 {
+        assert_eq!(ExitStatus::Paused, a.run_for(Budget::Steps(100)).unwrap());
+        assert_eq!(ExitStatus::Paused, b.run_for(Budget::Steps(100)).unwrap());
+    }
+
+    assert_eq!(300, a.steps);
+    assert!(a.io.output().chars().all(|c| c == 'a'));
+    assert!(b.io.output().chars().all(|c| c == 'b'));
+    assert_eq!(a.io.output().len(), b.io.output().len());
+}
+
+#[test]
+fn test_compile_threshold() {
+    for name in &["hello.bf", "control_flow.bf", "put_wide.bf", "random.bf"] {
+        let source = common::fixture(name);
+        let run = |threshold| {
+            Builder::new(source.as_str())
+                .io(VecIO::new(""))
+                .seed(1)
+                .compile_threshold(threshold)
+                .run()
+        };
+
+        let compiled = run(0);
+        compiled.result.unwrap();
+        assert_eq!(0, compiled.stats.blocks_interpreted, "{}", name);
+
+        for &threshold in &[1, 2, 10, u32::MAX] {
+            let tiered = run(threshold);
+            tiered.result.unwrap();
+            assert_eq!(compiled.io.output(), tiered.io.output(), "{} at {}", name, threshold);
+            assert_eq!(compiled.stack, tiered.stack, "{} at {}", name, threshold);
+            assert_eq!(compiled.steps, tiered.steps, "{} at {}", name, threshold);
+        }
+    }
+}
+
+#[test]
+fn test_hot_loop_compiled() {
+    // counts down from 100, so only the loop is hot
+    let source = "91+:*>1-:v\n     ^   _@";
+    let result = Builder::new(source)
+        .io(VecIO::new(""))
+        .compile_threshold(5)
+        .max_steps(1_000_000)
+        .run();
+    result.result.unwrap();
+    assert_eq!(1, result.stats.blocks_compiled);
+    assert!(result.stats.blocks_interpreted >= 5);
+    assert!(result.stats.steps_interpreted < result.steps / 2);
+}
+
+#[test]
+fn test_stats() {
+    let source = "91+:*>1-:v\n     ^   _@";
+    let result = Builder::new(source).io(VecIO::new("")).compile_threshold(5).run();
+    result.result.unwrap();
+    let stats = result.stats;
+    // each block is traced once, and only the loop is run often enough to be compiled from
+    // that trace
+    assert_eq!(3, stats.blocks_traced);
+    assert_eq!(1, stats.blocks_compiled);
+    assert!(stats.code_bytes > 0);
+    assert_eq!(0, stats.invalidations);
+    assert_eq!(100, stats.slow_paths.horizontal_if);
+    assert_eq!(0, stats.slow_paths.vertical_if);
+    assert_eq!(0, stats.slow_paths.random);
+    assert_eq!(0, stats.slow_paths.put);
+    assert_eq!(2, stats.peak_stack_depth);
+    let report = stats.report(result.steps);
+    assert!(report.starts_with(&format!("steps:              {}\n", result.steps)));
+    assert!(report.contains("\n  _:                100\n"));
+    assert!(report.ends_with("\npeak stack depth:   2\n"));
+
+    // each `p` throws away the compiled loop, which is traced and compiled again
+    let source = "5>:00p1-:v\n ^       _@";
+    let result = Builder::new(source).io(VecIO::new("")).compile_threshold(0).run();
+    result.result.unwrap();
+    let stats = result.stats;
+    assert_eq!(5, stats.slow_paths.put);
+    assert_eq!(5, stats.invalidations);
+    assert_eq!(stats.blocks_compiled, stats.blocks_traced);
+}
+
+#[test]
+fn test_stats_random() {
+    // every direction the `?` takes rejoins the loop, which runs it 24 times
+    let source = "55*>v    @\n>#v?>1-:!|\n  >>^     \n^        <";
+    let random = |builder: Builder| {
+        let result = builder.io(VecIO::new("")).run();
+        result.result.unwrap();
+        (result.stats.random_turns, result.stats.slow_paths.random)
+    };
+    let directions = || -> Box<Scripted> { Box::new("^>v<".parse().unwrap()) };
+
+    assert_eq!((24, 24), random(Builder::new(source).interpret(true)));
+    // compiled code only takes the slow path the first time it goes each way
+    let builder = Builder::new(source).directions(directions()).compile_threshold(0);
+    assert_eq!((24, 4), random(builder));
+    // including when it steps the generator inline
+    let (turns, slow) = random(Builder::new(source).seed(7).compile_threshold(0));
+    assert_eq!(24, turns);
+    assert!(slow <= 4);
+}
+
+#[test]
+fn test_trace() {
+    let buffer = SharedBuffer::default();
+    let result = Builder::new("12+ .@")
+        .io(VecIO::new(""))
+        .trace(Tracer::new(buffer.clone(), TraceLevel::Instruction))
+        .run();
+    result.result.unwrap();
+
+    let trace = buffer.contents();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(result.steps as usize, lines.len());
+    assert_eq!(r#"{"step":0,"pos":[0,0],"delta":[1,0],"instr":"1","top":null}"#, lines[0]);
+    assert_eq!(r#"{"step":3,"pos":[3,0],"delta":[1,0],"instr":" ","top":3}"#, lines[3]);
+}
+
+#[test]
+fn test_trace_blocks() {
+    // the loop counts down from 10, and is compiled after its first pass, so the trace has passes
+    // through interpreted blocks as well as compiled ones
+    let source = "91+>1-:v\n   ^   _@";
+    let buffer = SharedBuffer::default();
+    let result = Builder::new(source)
+        .io(VecIO::new(""))
+        .compile_threshold(1)
+        .trace(Tracer::new(buffer.clone(), TraceLevel::Block))
+        .run();
+    result.result.unwrap();
+    assert!(result.stats.blocks_interpreted > 0 && result.stats.blocks_compiled > 0);
+
+    // every step belongs to one pass through a block
+    let trace = buffer.contents();
+    let steps: u64 = trace
+        .lines()
+        .map(|line| line.rsplit(':').next().unwrap().trim_end_matches('}'))
+        .map(|steps| steps.parse::<u64>().unwrap())
+        .sum();
+    assert_eq!(result.steps, steps);
+    assert!(trace.lines().any(|line| line.contains(r#""pos":[7,1],"delta":[0,1],"instr":"_""#)));
+}
+
+#[test]
+fn test_dump_blocks() {
+    let buffer = SharedBuffer::default();
+    let result = Builder::new("91+>1-:v\n   ^   _@")
+        .io(VecIO::new(""))
+        .compile_threshold(0)
+        .dump_blocks(BlockDumper::new(buffer.clone()))
+        .run();
+    result.result.unwrap();
+
+    let dump = buffer.contents();
+    let blocks = dump.matches("block at").count();
+    assert_eq!(result.stats.blocks_compiled as usize, blocks);
+    assert!(dump.contains("block at (6, 1) moving (-1, 0)\n  instructions: 1-:\n"));
+    assert!(dump.contains("  steps: 1, loops: false, mutates: false, terminates: true"));
+    assert!(dump.contains("call rax"));
+    assert!(dump.contains("; Jit::push"));
+}
+
+#[test]
+fn test_perf() {
+    let result = Builder::new("91+>1-:v\n   ^   _@")
+        .io(VecIO::new(""))
+        .compile_threshold(0)
+        .perf(Perf::new(true).unwrap())
+        .run();
+    result.result.unwrap();
+    let map = std::fs::read_to_string(perf::map_path()).unwrap();
+    let jitdump = std::fs::read(perf::jitdump_path()).unwrap();
+    std::fs::remove_file(perf::map_path()).unwrap();
+    std::fs::remove_file(perf::jitdump_path()).unwrap();
+
+    assert_eq!(result.stats.blocks_compiled as usize, map.lines().count());
+    let mut fields = map.lines().next().unwrap().splitn(3, ' ');
+    usize::from_str_radix(fields.next().unwrap(), 16).unwrap();
+    usize::from_str_radix(fields.next().unwrap(), 16).unwrap();
+    assert_eq!(Some("befunge block at (0, 0) moving (1, 0)"), fields.next());
+
+    // the header is followed by a record for each block, and one more once the program is done
+    let read_u32 = |at: usize| u32::from_ne_bytes(jitdump[at..at + 4].try_into().unwrap());
+    assert_eq!(0x4A69_5444, read_u32(0));
+    let mut at = read_u32(8) as usize;
+    let mut records = Vec::new();
+    while at < jitdump.len() {
+        records.push(read_u32(at));
+        at += read_u32(at + 4) as usize;
+    }
+    assert_eq!(jitdump.len(), at);
+    let mut expected = vec![0; result.stats.blocks_compiled as usize];
+    expected.push(3);
+    assert_eq!(expected, records);
+}
+
+#[test]
+fn test_profile() {
+    let result = Builder::new("91+>1-:v\n   ^   _@").io(VecIO::new("")).profile(true).run();
+    result.result.unwrap();
+    let profile = result.profile.unwrap();
+
+    assert_eq!(1, profile.count(Pos::new(0, 0)));
+    assert_eq!(10, profile.count(Pos::new(4, 0)));
+    assert_eq!(9, profile.count(Pos::new(4, 1)));
+    assert_eq!(0, profile.count(Pos::new(0, 1)));
+    let (pos, delta, count) = profile.traces()[0];
+    assert_eq!((Pos::new(6, 1), Pos::west(), 9), (pos, delta, count.steps));
+    assert!(count.passes > 0);
+
+    let cells = space::Funge93::<i64>::from_string("91+>1-:v\n   ^   _@");
+    let mut csv = Vec::new();
+    profile.write_csv(&cells, &mut csv).unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(Some("kind,x,y,dx,dy,instr,steps,count"), lines.next());
+    assert_eq!(Some("cell,0,0,,,9,1,1"), lines.next());
+    assert!(csv.contains("\ntrace,6,1,-1,0,,9,"));
+
+    let mut report = Vec::new();
+    profile.write_report(&cells, ReportFormat::Csv, &mut report).unwrap();
+    assert_eq!(csv, String::from_utf8(report).unwrap());
+    let mut report = Vec::new();
+    profile.write_report(&cells, ReportFormat::Json, &mut report).unwrap();
+    let json = String::from_utf8(report).unwrap();
+    assert!(json.starts_with(r#"{"cells":[{"pos":[0,0],"instr":"9","count":1},"#));
+}
+
+fn assert_send<T: Send>() {}
+
+// checked when the test is compiled: a `Jit` can be built on one thread and run on another, such
+// as a server's worker
+#[test]
+fn test_send() {
+    assert_send::<Jit<StdIO, i64>>();
+}
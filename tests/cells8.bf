@@ -0,0 +1,6 @@
+25*5*.84*,88*2*.84*,08-88**.84*,88*88**01p01g.84*,"d"2*01p01g.@
+
+
+This test checks that 8-bit cells keep full values on the stack, like the
+reference implementation, and only narrow them to a signed byte when they're
+stored in funge space.
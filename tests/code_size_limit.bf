@@ -0,0 +1 @@
+82+"!dlrow olleH",,,,,,,,,,,,,@
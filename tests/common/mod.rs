@@ -0,0 +1,94 @@
+// Each test crate includes this module, and uses a different part of it.
+#![allow(dead_code)]
+
+use std::io::{Cursor, Write};
+use std::sync::{Arc, Mutex};
+
+use funjit::reader::Reader;
+use funjit::IO;
+
+/// Input and output held in memory.
+pub struct VecIO {
+    input: Reader<Cursor<Vec<u8>>>,
+    output: Vec<u8>,
+    /// Everything the program asked the user.
+    pub prompts: String,
+}
+
+impl VecIO {
+    pub fn new(input: &str) -> Self {
+        VecIO {
+            input: Reader::new(Cursor::new(input.as_bytes().to_vec())),
+            output: Vec::new(),
+            prompts: String::new(),
+        }
+    }
+
+    pub fn output(&self) -> &str {
+        std::str::from_utf8(&self.output).unwrap()
+    }
+
+    pub fn output_bytes(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl IO for VecIO {
+    fn input_char(&mut self) -> std::io::Result<Option<u8>> {
+        self.input.read_char()
+    }
+
+    fn input_number(&mut self) -> std::io::Result<Option<isize>> {
+        self.input.read_number()
+    }
+
+    fn output_char(&mut self, c: u8) -> std::io::Result<()> {
+        self.output.push(c);
+        Ok(())
+    }
+
+    fn output_number(&mut self, n: &dyn std::fmt::Display) -> std::io::Result<()> {
+        self.output.extend(n.to_string().bytes());
+        Ok(())
+    }
+
+    fn pending_input(&self) -> Vec<u8> {
+        self.input.pending().to_vec()
+    }
+
+    fn unread_input(&mut self, pending: &[u8]) {
+        for &c in pending {
+            self.input.unread(c);
+        }
+    }
+
+    fn prompt(&mut self, prompt: &str) -> std::io::Result<()> {
+        self.prompts.push_str(prompt);
+        Ok(())
+    }
+}
+
+/// A buffer that can still be read after it's been handed to a `Jit`, such as for a trace.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn fixture(name: &str) -> String {
+    let path = format!("{}/tests/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read_to_string(path).unwrap()
+}
@@ -0,0 +1,137 @@
+extern crate funjit;
+
+mod common;
+
+use common::VecIO;
+use funjit::debugger::Debugger;
+use funjit::Builder;
+
+// Run debugger commands against a program, returning everything they printed.
+fn debug(source: &str, commands: &[&str]) -> String {
+    let jit = Builder::new(source).io(VecIO::new("")).build();
+    let mut debugger = Debugger::new(jit);
+    let mut out = Vec::new();
+    for command in commands {
+        assert!(debugger.command(command, &mut out).unwrap());
+    }
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_step() {
+    let out = debug("12+.@", &["step", "step 2", "stack", "pc"]);
+    assert_eq!(
+        "pc (1, 0) delta (1, 0) at '2'\n\
+         pc (3, 0) delta (1, 0) at '.'\n\
+         [3]\n\
+         pc (3, 0) delta (1, 0) at '.'\n",
+        out
+    );
+}
+
+#[test]
+fn test_breakpoint() {
+    // the breakpoint is in the middle of a hot loop, which is otherwise compiled
+    let source = "91+:*>1-:v\n     ^   _@";
+    let out = debug(source, &["break 8 0", "continue", "stack", "continue", "stack"]);
+    assert_eq!(
+        "Breakpoint: pc (8, 0) delta (1, 0) at ':'\n\
+         [99]\n\
+         Breakpoint: pc (8, 0) delta (1, 0) at ':'\n\
+         [98]\n",
+        out
+    );
+}
+
+#[test]
+fn test_delete_breakpoint() {
+    let out = debug("1.2.@", &["break 2 0", "delete 2 0", "continue", "continue"]);
+    assert_eq!("Terminated after 5 steps\nThe program has finished\n", out);
+}
+
+#[test]
+fn test_watch_cell() {
+    let out = debug("911p 922p@", &["watch 2 2", "continue", "continue"]);
+    assert_eq!(
+        "Watchpoint: pc (9, 0) delta (1, 0) at '@'\nTerminated after 10 steps\n",
+        out
+    );
+}
+
+#[test]
+fn test_watch_stack() {
+    let out = debug("1234$$@", &["watch stack 3", "continue", "stack"]);
+    assert_eq!("Watchpoint: pc (3, 0) delta (1, 0) at '4'\n[1, 2, 3]\n", out);
+}
+
+#[test]
+fn test_view() {
+    let out = debug(">1v\n  2\n  @", &["step 3", "view 1"]);
+    let expected = [
+        "pc (2, 1) delta (0, 1) at '2'",
+        "   0 |>1v",
+        ">  1 |  2",
+        "        ^",
+        "   2 |  @",
+    ];
+    assert_eq!(expected.join("\n") + "\n", out);
+}
+
+#[test]
+fn test_unknown_command() {
+    let out = debug("@", &["frobnicate"]);
+    assert_eq!("Unknown command: frobnicate (try `help`)\n", out);
+}
+
+#[test]
+fn test_back() {
+    let out = debug("12+.@", &["step 3", "back", "stack", "back 5", "back"]);
+    assert_eq!(
+        "pc (3, 0) delta (1, 0) at '.'\n\
+         pc (2, 0) delta (1, 0) at '+'\n\
+         [1, 2]\n\
+         pc (0, 0) delta (1, 0) at '1'\n\
+         Already at the start of the program\n",
+        out
+    );
+}
+
+#[test]
+fn test_replay_input() {
+    let jit = Builder::new("&&+.@").io(VecIO::new("19 23")).build();
+    let mut debugger = Debugger::new(jit);
+    let mut out = Vec::new();
+    for command in &["continue", "goto 2", "stack", "continue"] {
+        assert!(debugger.command(command, &mut out).unwrap());
+    }
+
+    assert_eq!(
+        "Terminated after 5 steps\n\
+         pc (2, 0) delta (1, 0) at '+'\n\
+         [19, 23]\n\
+         Terminated after 5 steps\n",
+        String::from_utf8(out).unwrap()
+    );
+    // the input is replayed rather than read again, and the output isn't repeated
+    assert_eq!("42", debugger.jit.io.output());
+}
+
+#[test]
+fn test_replay_random() {
+    // `?` pushes a 1, a 2 or nothing, depending on the way it sends the IP around the playfield
+    let jit = Builder::new("?1\n2").io(VecIO::new("")).build();
+    let mut debugger = Debugger::new(jit);
+    let mut state = |command| {
+        let mut out = Vec::new();
+        debugger.command(command, &mut out).unwrap();
+        debugger.command("stack", &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    };
+
+    // going back to before a snapshot replays the directions taken from there
+    let later = state("goto 5000");
+    let earlier = state("goto 1500");
+    assert!(later.len() > earlier.len());
+    assert_eq!(later, state("goto 5000"));
+    assert_eq!(earlier, state("goto 1500"));
+}
@@ -0,0 +1,139 @@
+extern crate funjit;
+extern crate num_bigint;
+extern crate rand;
+
+mod common;
+
+use num_bigint::BigInt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use common::VecIO;
+use funjit::cell::{Cell, Char, Overflow};
+use funjit::jit::{Dialect, Jit, Options};
+use funjit::space;
+
+// Every instruction, with a few cells that aren't instructions, weighted towards spaces so that
+// programs have room to move around.
+const CELLS: &[u8] = b"0123456789+-*/%!`:\\$.,&~gp_|?^>v<#@\"       ax";
+// Without `*`, which can square a bignum until it runs out of memory.
+const BIG_CELLS: &[u8] = b"0123456789+-/%!`:\\$.,&~gp_|?^>v<#@\"       ax";
+
+const PROGRAMS: usize = 300;
+const WIDTH: usize = 8;
+const HEIGHT: usize = 4;
+
+fn programs(seed: u64, cells: &[u8]) -> Vec<String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..PROGRAMS)
+        .map(|_| {
+            let mut program = String::new();
+            for _ in 0..HEIGHT {
+                for _ in 0..WIDTH {
+                    program.push(cells[rng.gen_range(0..cells.len())] as char);
+                }
+                program.push('\n');
+            }
+            program
+        })
+        .collect()
+}
+
+// Everything a run of `program` did that can be seen from outside.
+fn run<C: Cell>(program: &str, options: &Options) -> String {
+    let mut jit = Jit::with_options(
+        space::Funge93::<C>::from_string(program),
+        VecIO::new("12 34 5 hello"),
+        options.clone(),
+    );
+    let result = jit.run().map_err(|err| err.to_string());
+
+    let mut cells = Vec::new();
+    for y in 0..space::HEIGHT {
+        for x in 0..space::WIDTH {
+            cells.push(jit.cells.get(x, y).to_string());
+        }
+    }
+    format!(
+        "result: {:?}\nsteps: {}\npc: {:?}\ndelta: {:?}\nstack: {:?}\noutput: {:?}\n\
+         prompts: {:?}\ncells: {:?}",
+        result,
+        jit.steps,
+        jit.pc,
+        jit.delta,
+        jit.stack.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        String::from_utf8_lossy(jit.io.output_bytes()),
+        jit.io.prompts,
+        cells,
+    )
+}
+
+// Check that random programs run the same whether they're interpreted or compiled, once blocks
+// have been run `compile_threshold` times.
+fn check<C: Cell>(seed: u64, cells: &[u8], compile_threshold: u32, options: Options) {
+    let compiled = Options {
+        seed: Some(seed),
+        max_steps: Some(5_000),
+        max_stack: Some(200),
+        compile_threshold,
+        ..options
+    };
+    let interpreted = Options {
+        interpret: true,
+        ..compiled.clone()
+    };
+
+    let failures: Vec<_> = programs(seed, cells)
+        .into_iter()
+        .filter(|program| run::<C>(program, &interpreted) != run::<C>(program, &compiled))
+        .collect();
+    assert!(failures.is_empty(), "{} programs differ:\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn test_random_programs() {
+    check::<i64>(1, CELLS, 0, Options::default());
+}
+
+#[test]
+fn test_random_programs_narrow() {
+    check::<i32>(
+        2,
+        CELLS,
+        0,
+        Options {
+            overflow: Overflow::Trap,
+            ..Options::default()
+        },
+    );
+}
+
+#[test]
+fn test_random_programs_char() {
+    check::<Char>(6, CELLS, 0, Options::default());
+}
+
+#[test]
+fn test_random_programs_98() {
+    check::<i64>(
+        3,
+        CELLS,
+        0,
+        Options {
+            dialect: Dialect::Befunge98,
+            overflow: Overflow::Saturate,
+            ..Options::default()
+        },
+    );
+}
+
+#[test]
+fn test_random_programs_big() {
+    check::<BigInt>(4, BIG_CELLS, 0, Options::default());
+}
+
+// Blocks that switch from being interpreted to compiled part way through the program.
+#[test]
+fn test_random_programs_tiered() {
+    check::<i64>(5, CELLS, 3, Options::default());
+}
@@ -0,0 +1 @@
+88*:*:*88**2*:*2*01-/.84*,88*:*:*88**2*:*2*01-%.82+,@
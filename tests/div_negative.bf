@@ -0,0 +1 @@
+07-2/.84*,07-2%.84*,702-/.84*,702-%.82+,@
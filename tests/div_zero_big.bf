@@ -0,0 +1 @@
+50/.84*,50%.82+,@
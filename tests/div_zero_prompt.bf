@@ -0,0 +1,5 @@
+&0/.84*,&.84*,&0%.@
+
+
+This test checks that dividing by zero asks for the result on standard error
+before reading it from the input, in between the numbers read by &.
@@ -0,0 +1,36 @@
+extern crate funjit;
+
+mod common;
+
+use common::VecIO;
+use funjit::gdb::{self, DebugInfo};
+use funjit::Builder;
+
+// GDB is told about blocks through a list shared by the whole process, so this is the only test
+// that registers any.
+#[test]
+fn test_registered() {
+    let mut jit = Builder::new("91+>1-:v\n   ^   _@")
+        .io(VecIO::new(""))
+        .compile_threshold(0)
+        .debug_info(DebugInfo::new("/tmp/loop.bf"))
+        .build();
+    jit.run().unwrap();
+
+    let symfiles = gdb::registered();
+    assert_eq!(jit.stats.blocks_compiled as usize, symfiles.len());
+    let contains = |symfile: &[u8], text: &str| {
+        symfile.windows(text.len()).any(|window| window == text.as_bytes())
+    };
+    for symfile in &symfiles {
+        assert_eq!(b"\x7fELF", &symfile[..4]);
+        assert!(contains(symfile, "loop.bf"));
+        assert!(contains(symfile, ".debug_line"));
+    }
+    let name = "befunge block at (0, 0) moving (1, 0)";
+    assert!(symfiles.iter().any(|symfile| contains(symfile, name)));
+
+    // blocks are unregistered once they're freed
+    drop(jit);
+    assert!(gdb::registered().is_empty());
+}
@@ -0,0 +1,5 @@
+21`.12`.11`.01-0`.001-`.@
+
+
+This test compares values that are greater, less, equal and negative with
+`, which pushes 1 only when the second value on the stack is greater.
@@ -0,0 +1 @@
+&&+.82+,@
@@ -0,0 +1 @@
+88*:*:*88**2*:*2*.@
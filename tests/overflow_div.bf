@@ -0,0 +1 @@
+088*:*:*88**2*:*-88*:*:*88**2*:*-01-/.@
@@ -0,0 +1 @@
+88*:*:*88**2*.84*,088*:*:*88**2*-1-1-.82+,@
@@ -0,0 +1 @@
+88*88**00p00g.82+,@
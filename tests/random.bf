@@ -0,0 +1,3 @@
+>    v
+@,"4"?"2",@
+     >"3",@
@@ -0,0 +1,5 @@
+1234:."a",5.55@
+
+
+This test fills the stack to exactly its limit of 5 with a number, a :, and a
+string, printing "4a5" in between, before pushing a sixth value over the limit.
@@ -0,0 +1,102 @@
+extern crate funjit;
+
+mod common;
+
+use common::VecIO;
+use funjit::cell::Char;
+use funjit::jit::Budget;
+use funjit::state::{self, StateError};
+use funjit::{Builder, ExitStatus};
+
+#[test]
+fn test_resume() {
+    for name in &["hello.bf", "control_flow.bf", "put_wide.bf", "random.bf"] {
+        let source = common::fixture(name);
+        let full = Builder::new(source.as_str()).io(VecIO::new("")).seed(1).run();
+        full.result.unwrap();
+
+        // `p` changes funge space, and `?` carries on from the saved generator
+        let mut first = Builder::new(source.as_str()).io(VecIO::new("")).seed(1).build();
+        assert_eq!(ExitStatus::Paused, first.run_for(Budget::Steps(full.steps / 2)).unwrap());
+        let mut saved = Vec::new();
+        state::save(&first, &mut saved).unwrap();
+
+        let mut second = Builder::new(source.as_str()).io(VecIO::new("")).seed(2).build();
+        state::load(&mut second, saved.as_slice()).unwrap();
+        assert_eq!(ExitStatus::Terminated, second.run().unwrap(), "{}", name);
+
+        let output = first.io.output().to_string() + second.io.output();
+        assert_eq!(full.io.output(), output, "{}", name);
+        assert_eq!(full.stack, second.stack, "{}", name);
+        assert_eq!(full.steps, second.steps, "{}", name);
+    }
+}
+
+#[test]
+fn test_pending_input() {
+    // reading the number reads the `x` after it too, which has to be saved for `~`
+    let mut first = Builder::new("&~,@").io(VecIO::new("12x")).build();
+    first.run_for(Budget::Steps(1)).unwrap();
+    let mut saved = Vec::new();
+    state::save(&first, &mut saved).unwrap();
+
+    let mut second = Builder::new("&~,@").io(VecIO::new("")).build();
+    state::load(&mut second, saved.as_slice()).unwrap();
+    second.run().unwrap();
+    assert_eq!("x", second.io.output());
+    assert_eq!(vec![12], second.stack);
+}
+
+#[test]
+fn test_version() {
+    let jit = Builder::new("@").io(VecIO::new("")).build();
+    let mut saved = Vec::new();
+    state::save(&jit, &mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap().replacen("\"version\":1", "\"version\":99", 1);
+
+    let mut jit = Builder::new("@").io(VecIO::new("")).build();
+    match state::load(&mut jit, saved.as_bytes()) {
+        Err(StateError::Version(99)) => (),
+        other => panic!("Unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_cells() {
+    let jit = Builder::new("@").io(VecIO::new("")).build();
+    let mut saved = Vec::new();
+    state::save(&jit, &mut saved).unwrap();
+
+    let mut jit = Builder::new("@").io(VecIO::new("")).cells::<Char>().build();
+    let err = state::load(&mut jit, saved.as_slice()).unwrap_err();
+    assert_eq!("Saved state has 64 cells, but the program uses 8 cells", err.to_string());
+}
+
+#[test]
+fn test_position() {
+    let jit = Builder::new("@").io(VecIO::new("")).build();
+    let mut saved = Vec::new();
+    state::save(&jit, &mut saved).unwrap();
+    let saved = String::from_utf8(saved).unwrap();
+
+    // neither the pc nor the delta can come from anywhere but the four arrows, and a file that
+    // claims otherwise leaves the program as it was
+    for (from, to) in &[
+        ("\"pc\":[0,0]", "\"pc\":[80,0]"),
+        ("\"pc\":[0,0]", "\"pc\":[0,-1]"),
+        ("\"delta\":[1,0]", "\"delta\":[2,0]"),
+        ("\"delta\":[1,0]", "\"delta\":[1,1]"),
+        ("\"delta\":[1,0]", "\"delta\":[0,0]"),
+    ] {
+        let bad = saved.replacen(from, to, 1);
+        assert_ne!(saved, bad);
+
+        let mut jit = Builder::new("1@").io(VecIO::new("")).build();
+        match state::load(&mut jit, bad.as_bytes()) {
+            Err(StateError::Format(_)) => (),
+            other => panic!("Unexpected result for {}: {:?}", to, other),
+        }
+        assert_eq!(ExitStatus::Terminated, jit.run().unwrap());
+        assert_eq!(vec![1], jit.stack);
+    }
+}
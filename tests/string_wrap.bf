@@ -0,0 +1,6 @@
+v
+>",,,,,,,@                                                                !olleh
+
+
+This test runs a string that wraps around the playfield back to the " that
+started it, pushing the rest of the row, and then prints the end of it.
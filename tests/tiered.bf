@@ -0,0 +1,15 @@
+"ih",,67*1+52*2p5                                    v
+                                                 >>v
+>>:.:3`.:1P.:2%.:7*8/.:9-!."xy"\$,&.~,02g,ab#@0_#^?v
+                                                  >v
+
+|                                               :-1<
+@
+
+ ^                                                   <
+
+
+This test runs a loop five times that uses every instruction, including `, "
+and cells that aren't instructions, with a `?` whose directions all join up
+again. With a compile threshold of 3, its blocks are interpreted for the first
+three times round and compiled for the rest.
@@ -0,0 +1,6 @@
+5>1a.1-:v
+ ^  xyz _@
+
+
+This test runs letters and other cells that aren't instructions, which do
+nothing, in a loop that prints "1" five times.
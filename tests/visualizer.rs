@@ -0,0 +1,57 @@
+extern crate funjit;
+
+use std::time::Duration;
+
+use funjit::jit::Budget;
+use funjit::space;
+use funjit::visualizer::{PaneIO, Visualizer};
+use funjit::{Builder, ExitStatus};
+
+fn visualizer(source: &str) -> Visualizer<i64> {
+    Visualizer::new(Builder::new(source).io(PaneIO::new()).build())
+}
+
+#[test]
+fn test_frame() {
+    let mut visualizer = visualizer("12.3v\n    @");
+    for _ in 0..4 {
+        visualizer.jit.run_for(Budget::Steps(1)).unwrap();
+    }
+
+    let frame = visualizer.frame();
+    let rows: Vec<&str> = frame.lines().collect();
+    assert_eq!(space::HEIGHT + 1 + 1, rows.len());
+    // the IP is about to run the `v`, with the stack beside the playfield and the output below
+    assert!(rows[0].starts_with("12.3\x1b[7mv\x1b[0m"), "{:?}", rows[0]);
+    assert!(rows[0].ends_with(" \u{2502} 3\x1b[K"), "{:?}", rows[0]);
+    assert!(rows[1].ends_with(" \u{2502} 1\x1b[K"), "{:?}", rows[1]);
+    assert!(rows[2].ends_with(" \u{2502} \x1b[K"), "{:?}", rows[2]);
+    assert_eq!("2\x1b[K", rows[space::HEIGHT + 1]);
+}
+
+#[test]
+fn test_run() {
+    let mut visualizer = visualizer("\"!iH\",,,@");
+    let mut out = Vec::new();
+    let status = visualizer.run(Duration::from_millis(0), &mut out).unwrap();
+    assert_eq!(ExitStatus::Terminated, status);
+    assert_eq!(b"Hi!", &visualizer.jit.io.output[..]);
+
+    // a frame is drawn after every step, and recently executed cells are highlighted
+    let out = String::from_utf8(out).unwrap();
+    assert_eq!(visualizer.jit.steps as usize, out.matches("\x1b[H").count());
+    assert!(out.ends_with("Hi!\x1b[K\n"));
+    assert!(out.contains("\x1b[33m,\x1b[0m"));
+}
+
+#[test]
+fn test_run_uncompiled() {
+    // stepping one cell at a time only runs whole blocks that are a single step long, the `v`
+    // before the `_` and the `@` after it, so they're the only blocks compiled, even when blocks
+    // are compiled the first time they're reached
+    let jit = Builder::new("91+>1-:v\n   ^   _@").io(PaneIO::new()).compile_threshold(0).build();
+    let mut visualizer = Visualizer::new(jit);
+    let status = visualizer.run(Duration::from_millis(0), &mut Vec::new()).unwrap();
+    assert_eq!(ExitStatus::Terminated, status);
+    assert_eq!(2, visualizer.jit.stats.blocks_compiled);
+}